@@ -1,7 +1,7 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use zkhash::{
     fields::{bls12::FpBLS12},
-    merkle_tree::merkle_tree_fp::MerkleTree,
+    merkle_tree::{merkle_tree_fp::MerkleTree, merkle_tree_sapling},
     neptune::{neptune::Neptune, neptune_instances::{
         NEPTUNE_BLS_4_PARAMS,
         // NEPTUNE_BLS_8_PARAMS,
@@ -21,6 +21,7 @@ use zkhash::{
         // POSEIDON2_BLS_4_PARAMS,
         // POSEIDON2_BLS_8_PARAMS,
     }},
+    pedersen_hash::pedersen_hash::SaplingMerkleCrh,
 };
 type Scalar = FpBLS12;
 
@@ -58,6 +59,55 @@ fn poseidon2(c: &mut Criterion, log_set_size: usize) {
     });
 }
 
+fn poseidon2_parallel(c: &mut Criterion, log_set_size: usize) {
+    let perm = Poseidon2::new(&POSEIDON2_BLS_3_PARAMS);
+    let mut mt = MerkleTree::new(perm);
+    let set_size = 1 << log_set_size;
+    let set: Vec<Scalar> = sample_set(set_size);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let id = format!("Poseidon2 BLS12 MT parallel (set_size = 2^{}, threads = {})", log_set_size, threads);
+
+    c.bench_function(&id, move |bench| {
+        bench.iter(|| {
+            mt.accumulate_parallel(black_box(&set), threads);
+        });
+    });
+}
+
+fn sample_set_sapling(set_size: usize) -> Vec<jubjub::Base> {
+    (0..set_size).map(|i| jubjub::Base::from(i as u64)).collect()
+}
+
+fn pedersen_sapling(c: &mut Criterion, log_set_size: usize) {
+    let mut mt = merkle_tree_sapling::MerkleTree::new(SaplingMerkleCrh::new());
+    let set_size = 1 << log_set_size;
+    let set = sample_set_sapling(set_size);
+
+    let id = format!("Pedersen Sapling MT (set_size = 2^{})", log_set_size);
+
+    c.bench_function(&id, move |bench| {
+        bench.iter(|| {
+            mt.accumulate(black_box(&set));
+        });
+    });
+}
+
+fn poseidon2_sapling(c: &mut Criterion, log_set_size: usize) {
+    let perm = Poseidon2::new(&POSEIDON2_BLS_3_PARAMS);
+    let mut mt = merkle_tree_sapling::MerkleTree::new(perm);
+    let set_size = 1 << log_set_size;
+    let set = sample_set_sapling(set_size);
+
+    let id = format!("Poseidon2 BLS12 Sapling MT (set_size = 2^{})", log_set_size);
+
+    c.bench_function(&id, move |bench| {
+        bench.iter(|| {
+            mt.accumulate(black_box(&set));
+        });
+    });
+}
+
 fn neptune(c: &mut Criterion, log_set_size: usize) {
     let perm = Neptune::new(&NEPTUNE_BLS_4_PARAMS);
     let mut mt = MerkleTree::new(perm);
@@ -94,6 +144,9 @@ fn criterion_benchmark_mt_bls(c: &mut Criterion) {
     for log_set_size in log_set_sizes {
         poseidon(c, log_set_size);
         poseidon2(c, log_set_size);
+        poseidon2_parallel(c, log_set_size);
+        poseidon2_sapling(c, log_set_size);
+        pedersen_sapling(c, log_set_size);
         gmimc(c, log_set_size);
         neptune(c, log_set_size);
     }
@@ -0,0 +1,63 @@
+use pasta_curves::group::ff::PrimeField as _;
+use std::convert::TryInto;
+
+use super::{
+    bls12::FpBLS12,
+    pallas::FpPallas,
+    utils::{from_canonical_bytes, to_canonical_bytes},
+};
+
+// Lossless conversions between the ark-ff fields and the field types of the
+// pasta_curves and jubjub crates over the same primes. Both sides use the
+// canonical 32 byte little-endian encoding, so every conversion is a bijection.
+
+pub fn pallas_to_ark(x: &pasta_curves::pallas::Base) -> FpPallas {
+    from_canonical_bytes(&x.to_repr()).unwrap()
+}
+
+pub fn ark_to_pallas(x: &FpPallas) -> pasta_curves::pallas::Base {
+    let bytes: [u8; 32] = to_canonical_bytes(x).try_into().unwrap();
+    pasta_curves::pallas::Base::from_repr(bytes).unwrap()
+}
+
+pub fn jubjub_to_ark(x: &jubjub::Base) -> FpBLS12 {
+    from_canonical_bytes(&x.to_bytes()).unwrap()
+}
+
+pub fn ark_to_jubjub(x: &FpBLS12) -> jubjub::Base {
+    let bytes: [u8; 32] = to_canonical_bytes(x).try_into().unwrap();
+    jubjub::Base::from_bytes(&bytes).unwrap()
+}
+
+#[cfg(test)]
+mod conversions_tests {
+    use super::*;
+    use crate::fields::utils::random_scalar;
+    use ark_ff::PrimeField;
+
+    #[test]
+    fn same_moduli() {
+        let minus_one = -FpPallas::from(1u64);
+        assert_eq!(ark_to_pallas(&minus_one), -pasta_curves::pallas::Base::one());
+        let minus_one = -FpBLS12::from(1u64);
+        assert_eq!(ark_to_jubjub(&minus_one), -jubjub::Base::one());
+        assert_eq!(FpPallas::MODULUS_BIT_SIZE, pasta_curves::pallas::Base::NUM_BITS);
+    }
+
+    #[test]
+    fn round_trips() {
+        for _ in 0..10 {
+            let x: FpPallas = random_scalar();
+            let y: FpPallas = random_scalar();
+            assert_eq!(pallas_to_ark(&ark_to_pallas(&x)), x);
+            assert_eq!(ark_to_pallas(&(x * y)), ark_to_pallas(&x) * ark_to_pallas(&y));
+
+            let x: FpBLS12 = random_scalar();
+            let y: FpBLS12 = random_scalar();
+            assert_eq!(jubjub_to_ark(&ark_to_jubjub(&x)), x);
+            assert_eq!(ark_to_jubjub(&(x + y)), ark_to_jubjub(&x) + ark_to_jubjub(&y));
+        }
+        assert_eq!(pallas_to_ark(&pasta_curves::pallas::Base::from(7u64)), FpPallas::from(7u64));
+        assert_eq!(jubjub_to_ark(&jubjub::Base::from(7u64)), FpBLS12::from(7u64));
+    }
+}
@@ -14,6 +14,8 @@ pub mod goldilocks;
 pub mod babybear;
 pub mod pallas;
 pub mod vesta;
+pub mod conversions;
+pub mod to_field_elements;
 pub mod utils;
 
 // sage:
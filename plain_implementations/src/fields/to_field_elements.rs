@@ -0,0 +1,278 @@
+use ark_ff::PrimeField;
+use pasta_curves::group::ff::PrimeField as _;
+
+use super::{
+    babybear::FpBabyBear,
+    bls12::FpBLS12,
+    bn256::FpBN256,
+    goldilocks::FpGoldiLocks,
+    pallas::FpPallas,
+    utils::{from_canonical_bytes, pack_bytes},
+    vesta::FpVesta,
+};
+use crate::poseidon2::poseidon2::Poseidon2;
+
+pub use zkhash_derive::ToFieldElements;
+
+// Canonical encoding of values as field elements. The encoding of every type
+// is self-delimiting, i.e., its length is determined by the type and the
+// elements read so far, so the concatenation used for tuples and derived
+// structs is injective:
+//  - bool: a single element 0 or 1
+//  - integers: little-endian limbs of min(MODULUS_BIT_SIZE - 1, 128) bits, as
+//    many as needed for the width of the type (a single element for fields
+//    larger than the type), signed integers as their two's complement and
+//    usize/isize as 64 bit integers
+//  - [u8; N]: packed with fields::utils::pack_bytes
+//  - Option: 0 for None, 1 followed by the value for Some
+//  - Vec: the length as a usize followed by the elements
+//  - tuples: the components in order
+//  - field elements: the element itself, the pasta_curves fields as the ark-ff
+//    field over the same prime
+//  - derived structs: the fields in declaration order, derived enums: the index
+//    of the variant followed by its fields
+pub trait ToFieldElements<F: PrimeField> {
+    fn write_elements(&self, out: &mut Vec<F>);
+
+    fn to_field_elements(&self) -> Vec<F> {
+        let mut out = Vec::new();
+        self.write_elements(&mut out);
+        out
+    }
+}
+
+// Sponge hash of the encoding of a value.
+pub fn hash_struct<F: PrimeField, T: ToFieldElements<F> + ?Sized>(perm: &Poseidon2<F>, value: &T) -> F {
+    hash_struct_many(perm, value, 1)[0]
+}
+
+// Sponge hash into `out_len` elements with a capacity of as many elements, for
+// small fields, e.g., 4 Goldilocks elements with a t = 8 permutation.
+pub fn hash_struct_many<F: PrimeField, T: ToFieldElements<F> + ?Sized>(
+    perm: &Poseidon2<F>,
+    value: &T,
+    out_len: usize,
+) -> Vec<F> {
+    perm.hash_many(&value.to_field_elements(), out_len)
+}
+
+pub fn limb_bits<F: PrimeField>() -> u32 {
+    (F::MODULUS_BIT_SIZE - 1).min(128)
+}
+
+fn write_uint<F: PrimeField>(x: u128, bits: u32, out: &mut Vec<F>) {
+    let limb = limb_bits::<F>();
+    let mask = if limb == 128 { u128::MAX } else { (1 << limb) - 1 };
+    let mut x = x;
+    for _ in 0..bits.div_ceil(limb) {
+        out.push(F::from(x & mask));
+        x = x.checked_shr(limb).unwrap_or(0);
+    }
+}
+
+macro_rules! impl_uint {
+    ($($t:ty => $bits:expr),*) => {$(
+        impl<F: PrimeField> ToFieldElements<F> for $t {
+            fn write_elements(&self, out: &mut Vec<F>) {
+                write_uint(*self as u128, $bits, out);
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_int {
+    ($($t:ty as $u:ty => $bits:expr),*) => {$(
+        impl<F: PrimeField> ToFieldElements<F> for $t {
+            fn write_elements(&self, out: &mut Vec<F>) {
+                write_uint(*self as $u as u128, $bits, out);
+            }
+        }
+    )*};
+}
+
+impl_uint!(u8 => 8, u16 => 16, u32 => 32, u64 => 64, u128 => 128, usize => 64);
+impl_int!(i8 as u8 => 8, i16 as u16 => 16, i32 as u32 => 32, i64 as u64 => 64, i128 as u128 => 128, isize as u64 => 64);
+
+impl<F: PrimeField> ToFieldElements<F> for bool {
+    fn write_elements(&self, out: &mut Vec<F>) {
+        out.push(F::from(*self));
+    }
+}
+
+impl<F: PrimeField, const N: usize> ToFieldElements<F> for [u8; N] {
+    fn write_elements(&self, out: &mut Vec<F>) {
+        out.extend(pack_bytes::<F>(self));
+    }
+}
+
+impl<F: PrimeField, T: ToFieldElements<F>> ToFieldElements<F> for Option<T> {
+    fn write_elements(&self, out: &mut Vec<F>) {
+        match self {
+            None => out.push(F::zero()),
+            Some(value) => {
+                out.push(F::one());
+                value.write_elements(out);
+            }
+        }
+    }
+}
+
+impl<F: PrimeField, T: ToFieldElements<F>> ToFieldElements<F> for Vec<T> {
+    fn write_elements(&self, out: &mut Vec<F>) {
+        self.len().write_elements(out);
+        self.iter().for_each(|value| value.write_elements(out));
+    }
+}
+
+impl<F: PrimeField, T: ToFieldElements<F> + ?Sized> ToFieldElements<F> for &T {
+    fn write_elements(&self, out: &mut Vec<F>) {
+        (**self).write_elements(out);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<F: PrimeField, $($name: ToFieldElements<F>),+> ToFieldElements<F> for ($($name,)+) {
+            fn write_elements(&self, out: &mut Vec<F>) {
+                $(self.$idx.write_elements(out);)+
+            }
+        }
+    };
+}
+
+impl<F: PrimeField> ToFieldElements<F> for () {
+    fn write_elements(&self, _out: &mut Vec<F>) {}
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, G 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, G 5, H 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, G 5, H 6, I 7);
+
+macro_rules! impl_field {
+    ($($f:ty),*) => {$(
+        impl ToFieldElements<$f> for $f {
+            fn write_elements(&self, out: &mut Vec<$f>) {
+                out.push(*self);
+            }
+        }
+    )*};
+}
+
+impl_field!(FpBN256, FpBLS12, FpPallas, FpVesta, FpGoldiLocks, FpBabyBear);
+
+impl ToFieldElements<FpPallas> for pasta_curves::pallas::Base {
+    fn write_elements(&self, out: &mut Vec<FpPallas>) {
+        out.push(from_canonical_bytes(&self.to_repr()).unwrap());
+    }
+}
+
+impl ToFieldElements<FpVesta> for pasta_curves::vesta::Base {
+    fn write_elements(&self, out: &mut Vec<FpVesta>) {
+        out.push(from_canonical_bytes(&self.to_repr()).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod to_field_elements_tests {
+    use super::*;
+    use crate::fields::utils::random_scalar;
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+    use crate::poseidon2::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+
+    type Scalar = FpBN256;
+
+    #[derive(ToFieldElements)]
+    struct Account {
+        id: u64,
+        frozen: bool,
+        owner: [u8; 40],
+        balance: Scalar,
+        memo: Option<Vec<u8>>,
+    }
+
+    #[derive(ToFieldElements)]
+    struct Pair<T>(T, T);
+
+    #[derive(ToFieldElements)]
+    enum Transaction {
+        Transfer { from: u32, to: u32, amount: u128 },
+        Burn(u32),
+        Noop,
+    }
+
+    fn elements<T: ToFieldElements<Scalar>>(value: &T) -> Vec<Scalar> {
+        value.to_field_elements()
+    }
+
+    fn ints(values: &[u64]) -> Vec<Scalar> {
+        values.iter().map(|v| Scalar::from(*v)).collect()
+    }
+
+    #[test]
+    fn derived_struct() {
+        let balance: Scalar = random_scalar();
+        let account = Account {
+            id: 7,
+            frozen: true,
+            owner: [1u8; 40],
+            balance,
+            memo: Some(vec![5, 6]),
+        };
+
+        let mut expected = ints(&[7, 1]);
+        expected.extend(pack_bytes::<Scalar>(&[1u8; 40]));
+        expected.push(balance);
+        expected.extend(ints(&[1, 2, 5, 6]));
+        assert_eq!(elements(&account), expected);
+
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        assert_eq!(hash_struct(&perm, &account), perm.hash(&expected));
+        assert_eq!(elements(&Pair(3u8, 4u8)), elements(&(3u8, 4u8)));
+
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        let pair = Pair(3u8, 4u8);
+        let expected = perm.hash_many_with_rate(&[FpGoldiLocks::from(3u64), FpGoldiLocks::from(4u64)], 4, 4);
+        assert_eq!(hash_struct_many(&perm, &pair, 4), expected);
+    }
+
+    #[test]
+    fn derived_enum() {
+        let transfer = Transaction::Transfer { from: 1, to: 2, amount: 3 };
+        assert_eq!(elements(&transfer), ints(&[0, 1, 2, 3]));
+        assert_eq!(elements(&Transaction::Burn(9)), ints(&[1, 9]));
+        assert_eq!(elements(&Transaction::Noop), ints(&[2]));
+    }
+
+    #[test]
+    fn injective() {
+        assert_ne!(elements(&(vec![1u8], Vec::<u8>::new())), elements(&(Vec::<u8>::new(), vec![1u8])));
+        assert_ne!(elements(&Some(None::<u8>)), elements(&None::<Option<u8>>));
+        assert_ne!(elements(&Some(0u8)), elements(&(None::<u8>, 0u8)));
+        assert_eq!(elements(&-1i8), ints(&[255]));
+        assert_eq!(elements(&u128::MAX), vec![Scalar::from(u128::MAX)]);
+    }
+
+    #[test]
+    fn small_fields() {
+        // Goldilocks limbs have 63 bits and BabyBear limbs 30 bits
+        let x: Vec<FpGoldiLocks> = u64::MAX.to_field_elements();
+        assert_eq!(x, vec![FpGoldiLocks::from((1u64 << 63) - 1), FpGoldiLocks::from(1u64)]);
+        let x: Vec<FpBabyBear> = (u32::MAX, true).to_field_elements();
+        assert_eq!(x, vec![FpBabyBear::from((1u64 << 30) - 1), FpBabyBear::from(3u64), FpBabyBear::from(1u64)]);
+        let x: Vec<FpBabyBear> = [0u8; 7].to_field_elements();
+        assert_eq!(x.len(), 3);
+    }
+
+    #[test]
+    fn pasta_fields() {
+        let x = pasta_curves::pallas::Base::from(5u64);
+        assert_eq!(x.to_field_elements(), vec![FpPallas::from(5u64)]);
+        let y = -pasta_curves::vesta::Base::one();
+        assert_eq!(y.to_field_elements(), vec![-FpVesta::from(1u64)]);
+    }
+}
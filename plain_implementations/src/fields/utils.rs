@@ -1,4 +1,4 @@
-use ark_ff::{PrimeField};
+use ark_ff::{BigInteger, PrimeField};
 // use rand::{thread_rng, Rng};
 // use sha3::digest::XofReader;
 // use std::cmp::min;
@@ -25,6 +25,45 @@ pub fn random_scalar_without_0<F: PrimeField>() -> F {
     }
 }
 
+// Number of bytes which always fit into a single field element.
+pub fn bytes_per_element<F: PrimeField>() -> usize {
+    ((F::MODULUS_BIT_SIZE - 1) / 8) as usize
+}
+
+// Reads every chunk of bytes_per_element bytes as a little-endian integer.
+// Injective for inputs of a fixed length.
+pub fn pack_bytes<F: PrimeField>(bytes: &[u8]) -> Vec<F> {
+    bytes
+        .chunks(bytes_per_element::<F>())
+        .map(F::from_le_bytes_mod_order)
+        .collect()
+}
+
+// Length of the canonical encoding, i.e., the modulus rounded up to full bytes.
+pub fn canonical_byte_len<F: PrimeField>() -> usize {
+    F::MODULUS_BIT_SIZE.div_ceil(8) as usize
+}
+
+// Little-endian bytes of the reduced representative.
+pub fn to_canonical_bytes<F: PrimeField>(x: &F) -> Vec<u8> {
+    let mut bytes = x.into_bigint().to_bytes_le();
+    bytes.truncate(canonical_byte_len::<F>());
+    bytes
+}
+
+// Rejects encodings of the wrong length and non-reduced representatives.
+pub fn from_canonical_bytes<F: PrimeField>(bytes: &[u8]) -> Option<F> {
+    if bytes.len() != canonical_byte_len::<F>() {
+        return None;
+    }
+    let x = F::from_le_bytes_mod_order(bytes);
+    if to_canonical_bytes(&x) == bytes {
+        Some(x)
+    } else {
+        None
+    }
+}
+
 //-----------------------------------------------------------------------------
 // pub fn from_u64<F: PrimeField>(val: u64) -> F {
 //     F::from_repr(F::Repr::from(val)).unwrap()
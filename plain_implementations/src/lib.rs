@@ -4,11 +4,15 @@
 #![cfg_attr(feature = "asm", feature(asm))]
 
 pub extern crate ark_ff;
+// lets the derive macros refer to ::zkhash from within this crate
+extern crate self as zkhash;
 
 pub mod fields;
 pub mod gmimc;
 pub mod merkle_tree;
 pub mod neptune;
+pub mod pedersen_hash;
 pub mod poseidon;
 pub mod poseidon2;
+pub mod sinsemilla;
 pub mod utils;
@@ -0,0 +1,298 @@
+use ark_ff::PrimeField;
+use std::collections::BTreeMap;
+
+use super::merkle_tree::{climb, Compression, MerkleProof};
+use super::merkle_tree_fp::{FpCompression, MerkleTreeHash, SpongeHash};
+use super::stored_merkle_tree::StoredMerkleTree;
+use crate::poseidon2::poseidon2::Poseidon2;
+
+// Leaves form a linked list sorted by value. A next_value of zero marks the end
+// of the list, so zero itself is reserved for the initial leaf.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexedLeaf<F: PrimeField> {
+    pub value: F,
+    pub next_index: usize,
+    pub next_value: F,
+}
+
+impl<F: PrimeField> IndexedLeaf<F> {
+    pub fn hash<P: SpongeHash<F>>(&self, perm: &P) -> F {
+        perm.hash(&[self.value, F::from(self.next_index as u64), self.next_value])
+    }
+
+    // true if value lies strictly between this leaf and its successor
+    pub fn is_low_leaf_of(&self, value: &F) -> bool {
+        self.value < *value && (self.next_value > *value || self.next_value.is_zero())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InsertionWitness<F: PrimeField> {
+    pub low_index: usize,
+    // low leaf before the insertion and its path in the old tree
+    pub low_leaf: IndexedLeaf<F>,
+    pub low_path: MerkleProof<F>,
+    pub new_index: usize,
+    pub new_leaf: IndexedLeaf<F>,
+    // path of the empty slot after the low leaf was updated
+    pub new_path: MerkleProof<F>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedProof<F: PrimeField> {
+    pub index: usize,
+    pub leaf: IndexedLeaf<F>,
+    pub path: MerkleProof<F>,
+}
+
+#[derive(Clone, Debug)]
+pub struct IndexedMerkleTree<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone = Poseidon2<F>> {
+    perm: P,
+    tree: StoredMerkleTree<FpCompression<F, P>>,
+    leaves: Vec<IndexedLeaf<F>>,
+    // value -> leaf index
+    sorted: BTreeMap<F, usize>,
+}
+
+impl<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone> IndexedMerkleTree<F, P> {
+    // Empty slots hold the zero node, slot 0 holds the initial leaf (0, 0, 0).
+    pub fn new(perm: P, depth: usize) -> Self {
+        let mut tree = StoredMerkleTree::with_depth(FpCompression::new(perm.clone()), depth);
+        let initial = IndexedLeaf {
+            value: F::zero(),
+            next_index: 0,
+            next_value: F::zero(),
+        };
+        tree.update(0, initial.hash(&perm)).unwrap();
+        let mut sorted = BTreeMap::new();
+        sorted.insert(F::zero(), 0);
+        IndexedMerkleTree {
+            perm,
+            tree,
+            leaves: vec![initial],
+            sorted,
+        }
+    }
+
+    pub fn root(&self) -> F {
+        self.tree.root().unwrap()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.tree.depth()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn leaf(&self, index: usize) -> &IndexedLeaf<F> {
+        &self.leaves[index]
+    }
+
+    fn low_index(&self, value: &F) -> usize {
+        *self.sorted.range(..*value).next_back().unwrap().1
+    }
+
+    // Returns None if the value is already contained (or zero).
+    pub fn insert(&mut self, value: F) -> Option<InsertionWitness<F>> {
+        if self.sorted.contains_key(&value) {
+            return None;
+        }
+        let new_index = self.leaves.len();
+        assert!(new_index < self.tree.num_leaves(), "tree is full");
+
+        let low_index = self.low_index(&value);
+        let low_leaf = self.leaves[low_index];
+        let low_path = self.tree.prove(low_index).unwrap();
+
+        let updated_low = IndexedLeaf {
+            value: low_leaf.value,
+            next_index: new_index,
+            next_value: value,
+        };
+        self.tree.update(low_index, updated_low.hash(&self.perm)).unwrap();
+        self.leaves[low_index] = updated_low;
+
+        let new_leaf = IndexedLeaf {
+            value,
+            next_index: low_leaf.next_index,
+            next_value: low_leaf.next_value,
+        };
+        let new_path = self.tree.prove(new_index).unwrap();
+        self.tree.update(new_index, new_leaf.hash(&self.perm)).unwrap();
+        self.leaves.push(new_leaf);
+        self.sorted.insert(value, new_index);
+
+        Some(InsertionWitness {
+            low_index,
+            low_leaf,
+            low_path,
+            new_index,
+            new_leaf,
+            new_path,
+        })
+    }
+
+    pub fn prove_membership(&self, value: &F) -> Option<IndexedProof<F>> {
+        let index = *self.sorted.get(value)?;
+        Some(IndexedProof {
+            index,
+            leaf: self.leaves[index],
+            path: self.tree.prove(index).unwrap(),
+        })
+    }
+
+    // The proof opens the low leaf of the value.
+    pub fn prove_non_membership(&self, value: &F) -> Option<IndexedProof<F>> {
+        if self.sorted.contains_key(value) {
+            return None;
+        }
+        let index = self.low_index(value);
+        Some(IndexedProof {
+            index,
+            leaf: self.leaves[index],
+            path: self.tree.prove(index).unwrap(),
+        })
+    }
+}
+
+fn opens<F: PrimeField, P: MerkleTreeHash<F>>(
+    compression: &mut FpCompression<F, P>,
+    root: &F,
+    index: usize,
+    leaf: &F,
+    path: &MerkleProof<F>,
+) -> bool {
+    index >> path.siblings.len() == 0 && climb(compression, index, leaf, &path.siblings) == *root
+}
+
+pub fn verify_membership<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone>(
+    perm: &P,
+    root: &F,
+    value: &F,
+    proof: &IndexedProof<F>,
+) -> bool {
+    let mut compression = FpCompression::new(perm.clone());
+    proof.leaf.value == *value && opens(&mut compression, root, proof.index, &proof.leaf.hash(perm), &proof.path)
+}
+
+pub fn verify_non_membership<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone>(
+    perm: &P,
+    root: &F,
+    value: &F,
+    proof: &IndexedProof<F>,
+) -> bool {
+    let mut compression = FpCompression::new(perm.clone());
+    proof.leaf.is_low_leaf_of(value)
+        && opens(&mut compression, root, proof.index, &proof.leaf.hash(perm), &proof.path)
+}
+
+pub fn verify_insertion<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone>(
+    perm: &P,
+    old_root: &F,
+    new_root: &F,
+    value: &F,
+    witness: &InsertionWitness<F>,
+) -> bool {
+    let mut compression = FpCompression::new(perm.clone());
+    let low = &witness.low_leaf;
+    if !low.is_low_leaf_of(value)
+        || !opens(&mut compression, old_root, witness.low_index, &low.hash(perm), &witness.low_path)
+    {
+        return false;
+    }
+
+    let updated_low = IndexedLeaf {
+        value: low.value,
+        next_index: witness.new_index,
+        next_value: *value,
+    };
+    let intermediate = climb(
+        &mut compression,
+        witness.low_index,
+        &updated_low.hash(perm),
+        &witness.low_path.siblings,
+    );
+
+    let expected_new = IndexedLeaf {
+        value: *value,
+        next_index: low.next_index,
+        next_value: low.next_value,
+    };
+    let empty = compression.zero();
+    if witness.new_leaf != expected_new
+        || witness.new_path.siblings.len() != witness.low_path.siblings.len()
+        || !opens(&mut compression, &intermediate, witness.new_index, &empty, &witness.new_path)
+    {
+        return false;
+    }
+    opens(&mut compression, new_root, witness.new_index, &expected_new.hash(perm), &witness.new_path)
+}
+
+#[cfg(test)]
+mod indexed_merkle_tree_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+
+    type Scalar = FpBN256;
+
+    fn perm() -> Poseidon2<Scalar> {
+        Poseidon2::new(&POSEIDON2_BN256_PARAMS)
+    }
+
+    #[test]
+    fn insertion_witnesses() {
+        let perm = perm();
+        let mut tree = IndexedMerkleTree::new(perm.clone(), 4);
+        for v in [30u64, 10, 20, 50, 40] {
+            let value = Scalar::from(v);
+            let old_root = tree.root();
+            let witness = tree.insert(value).unwrap();
+            let new_root = tree.root();
+            assert!(verify_insertion(&perm, &old_root, &new_root, &value, &witness));
+            assert!(!verify_insertion(&perm, &old_root, &new_root, &Scalar::from(v + 1), &witness));
+        }
+        assert!(tree.insert(Scalar::from(20u64)).is_none());
+        assert_eq!(tree.len(), 6);
+
+        // the linked list is sorted
+        let mut leaf = *tree.leaf(0);
+        let mut values = vec![];
+        while leaf.next_value != Scalar::from(0u64) {
+            values.push(leaf.next_value);
+            leaf = *tree.leaf(leaf.next_index);
+        }
+        let expected: Vec<Scalar> = [10u64, 20, 30, 40, 50].iter().map(|v| Scalar::from(*v)).collect();
+        assert_eq!(values, expected);
+    }
+
+    #[test]
+    fn membership_and_non_membership() {
+        let perm = perm();
+        let mut tree = IndexedMerkleTree::new(perm.clone(), 5);
+        let values: Vec<Scalar> = (0..10).map(|_| random_scalar()).collect();
+        for v in values.iter() {
+            tree.insert(*v);
+        }
+        let root = tree.root();
+
+        for v in values.iter() {
+            let proof = tree.prove_membership(v).unwrap();
+            assert!(verify_membership(&perm, &root, v, &proof));
+            assert!(!verify_non_membership(&perm, &root, v, &proof));
+            assert!(tree.prove_non_membership(v).is_none());
+        }
+        for _ in 0..10 {
+            let v = random_scalar();
+            let proof = tree.prove_non_membership(&v).unwrap();
+            assert!(verify_non_membership(&perm, &root, &v, &proof));
+            assert!(!verify_membership(&perm, &root, &v, &proof));
+        }
+    }
+}
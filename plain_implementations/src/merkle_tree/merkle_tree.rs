@@ -0,0 +1,390 @@
+use std::fmt::Debug;
+use std::thread;
+
+use super::padding::{self, OddRule, Padding};
+use super::stored_merkle_tree::StoredMerkleTree;
+
+// Two-to-one compression of tree nodes. `level` is the height of the two input
+// nodes (0 for leaves) and `position` is the index of the resulting node within
+// its level. Implementations are free to ignore both.
+pub trait Compression {
+    type Node: Clone + PartialEq + Debug;
+    fn compress(&mut self, level: usize, position: usize, input: &[&Self::Node; 2]) -> Self::Node;
+    // leaf used for zero padding
+    fn zero(&self) -> Self::Node;
+    // encoding of the number of leaves for length-bound roots
+    fn length(&self, len: usize) -> Self::Node;
+}
+
+#[derive(Clone, Debug)]
+pub struct MerkleTree<C: Compression> {
+    compression: C,
+    padding: Padding<C::Node>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof<N> {
+    pub siblings: Vec<N>,
+}
+
+// A fully stored tree whose top is cut off at `cap_height`: levels[0] are the
+// leaves, the last level holds the 2^cap_height cap nodes.
+#[derive(Clone, Debug)]
+pub struct CappedMerkleTree<N> {
+    levels: Vec<Vec<N>>,
+    cap_height: usize,
+}
+
+impl<N: Clone> CappedMerkleTree<N> {
+    pub fn cap(&self) -> &[N] {
+        self.levels.last().unwrap()
+    }
+
+    pub fn cap_height(&self) -> usize {
+        self.cap_height
+    }
+
+    pub fn leaves(&self) -> &[N] {
+        &self.levels[0]
+    }
+
+    pub fn prove(&self, index: usize) -> MerkleProof<N> {
+        assert!(index < self.levels[0].len());
+        let siblings = self.levels[..self.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(lv, nodes)| nodes[(index >> lv) ^ 1].to_owned())
+            .collect();
+        MerkleProof { siblings }
+    }
+}
+
+impl<C: Compression> MerkleTree<C> {
+    pub fn from_compression(compression: C, padding: Padding<C::Node>) -> Self {
+        MerkleTree {
+            compression,
+            padding,
+        }
+    }
+
+    pub fn compression(&self) -> &C {
+        &self.compression
+    }
+
+    pub fn compression_mut(&mut self) -> &mut C {
+        &mut self.compression
+    }
+
+    pub fn padding(&self) -> &Padding<C::Node> {
+        &self.padding
+    }
+
+    pub fn accumulate(&mut self, set: &[C::Node]) -> C::Node {
+        let zero = self.compression.zero();
+        let length = self.compression.length(set.len());
+        let compression = &mut self.compression;
+        padding::accumulate(set, &self.padding, &zero, length, |lv, pos, inp| {
+            compression.compress(lv, pos, inp)
+        })
+    }
+
+    // Splits the padded leaf level into aligned subtrees, reduces them on up to
+    // `threads` worker threads, and finishes the top levels sequentially. The
+    // root is the same as the one of `accumulate`.
+    pub fn accumulate_parallel(&mut self, set: &[C::Node], threads: usize) -> C::Node
+    where
+        C: Clone + Send,
+        C::Node: Send + Sync,
+    {
+        let nodes = padding::prepare(set, &self.padding, &self.compression.zero());
+        let prepared_len = nodes.len();
+        let chunk_size = prepared_len.div_ceil(threads.max(1)).next_power_of_two().max(2);
+        if threads <= 1 || chunk_size >= prepared_len {
+            return self.accumulate(set);
+        }
+        let rounds = chunk_size.trailing_zeros() as usize;
+        let last = (prepared_len - 1) / chunk_size;
+
+        let results: Vec<(C::Node, OddRule<C::Node>)> = thread::scope(|s| {
+            let handles: Vec<_> = nodes
+                .chunks(chunk_size)
+                .enumerate()
+                .map(|(j, chunk)| {
+                    let mut compression = self.compression.clone();
+                    // only the rightmost subtree can have odd levels
+                    let mut odd = if j == last {
+                        OddRule::new(&self.padding)
+                    } else {
+                        OddRule::Never
+                    };
+                    s.spawn(move || {
+                        let mut compress =
+                            |lv: usize, pos: usize, inp: &[&C::Node; 2]| compression.compress(lv, pos, inp);
+                        let mut root = padding::reduce(
+                            chunk.to_owned(),
+                            0,
+                            j * chunk_size,
+                            Some(rounds),
+                            &mut odd,
+                            &mut compress,
+                        );
+                        (root.remove(0), odd)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut odd = results.last().unwrap().1.to_owned();
+        let roots = results.into_iter().map(|(root, _)| root).collect();
+        let length = self.compression.length(set.len());
+        let compression = &mut self.compression;
+        let mut compress = |lv: usize, pos: usize, inp: &[&C::Node; 2]| compression.compress(lv, pos, inp);
+        let root = padding::reduce(roots, rounds, 0, None, &mut odd, &mut compress).remove(0);
+        padding::finalize(root, prepared_len, &self.padding, length, &mut compress)
+    }
+
+    // Height of the tree over `set_size` leaves under the configured padding.
+    // Promoted nodes have no sibling, so Promote requires a power of two.
+    fn depth(&self, set_size: usize) -> Option<usize> {
+        let prepared_len = padding::prepared_len(set_size, &self.padding);
+        if matches!(self.padding, Padding::Promote) && !prepared_len.is_power_of_two() {
+            return None;
+        }
+        Some(prepared_len.next_power_of_two().trailing_zeros() as usize)
+    }
+
+    // Stores all levels below the cap of the tree over the padded set. The cap
+    // of height 0 is the root of `accumulate`, for LengthBound every cap node is
+    // compressed with the number of leaves.
+    pub fn build_with_cap(&mut self, set: &[C::Node], cap_height: usize) -> CappedMerkleTree<C::Node> {
+        let depth = self
+            .depth(set.len())
+            .expect("caps of promoted trees require a power of two number of leaves");
+        assert!(cap_height <= depth);
+
+        let zero = self.compression.zero();
+        let mut odd = OddRule::new(&self.padding);
+        let compression = &mut self.compression;
+        let mut compress = |lv: usize, pos: usize, inp: &[&C::Node; 2]| compression.compress(lv, pos, inp);
+
+        let mut levels = vec![padding::prepare(set, &self.padding, &zero)];
+        for lv in 0..depth - cap_height {
+            // keep the empty subtree roots as siblings of the stored levels
+            if let OddRule::Empty(empty) = &odd {
+                if levels[lv].len() % 2 == 1 {
+                    let empty = empty.to_owned();
+                    levels[lv].push(empty);
+                }
+            }
+            let nodes = padding::reduce(levels[lv].to_owned(), lv, 0, Some(1), &mut odd, &mut compress);
+            levels.push(nodes);
+        }
+        let cap = levels.last_mut().unwrap();
+        if let OddRule::Empty(empty) = &odd {
+            cap.resize(1 << cap_height, empty.to_owned());
+        }
+        if matches!(self.padding, Padding::LengthBound) {
+            let length = self.compression.length(set.len());
+            for (i, node) in cap.iter_mut().enumerate() {
+                *node = self.compression.compress(depth, i, &[node, &length]);
+            }
+        }
+        CappedMerkleTree { levels, cap_height }
+    }
+
+    // Stores all levels of the tree over `set`, padded as configured, for later
+    // updates. The root matches `accumulate` only if the padded leaf level is a
+    // power of two of at least 2 and the root is not length-bound, other trees
+    // are rejected.
+    pub fn build_stored(&self, set: &[C::Node]) -> StoredMerkleTree<C>
+    where
+        C: Clone,
+    {
+        let leaves = padding::prepare(set, &self.padding, &self.compression.zero());
+        assert!(
+            leaves.len() >= 2 && leaves.len().is_power_of_two() && !matches!(self.padding, Padding::LengthBound),
+            "the padding does not produce a complete stored tree"
+        );
+        StoredMerkleTree::new(self.compression.clone(), leaves)
+    }
+
+    pub fn climb(&mut self, index: usize, leaf: &C::Node, path: &[C::Node]) -> C::Node {
+        climb(&mut self.compression, index, leaf, path)
+    }
+
+    // Verifies a proof relative to a cap of the tree over `set_size` leaves.
+    pub fn verify_with_cap(
+        &mut self,
+        cap: &[C::Node],
+        set_size: usize,
+        index: usize,
+        leaf: &C::Node,
+        proof: &MerkleProof<C::Node>,
+    ) -> bool {
+        let depth = match self.depth(set_size) {
+            Some(depth) => depth,
+            None => return false,
+        };
+        if !cap.len().is_power_of_two() || index >= set_size.max(1) {
+            return false;
+        }
+        let cap_height = cap.len().trailing_zeros() as usize;
+        if cap_height > depth || proof.siblings.len() != depth - cap_height {
+            return false;
+        }
+        let cap_index = index >> proof.siblings.len();
+        let mut node = self.climb(index, leaf, &proof.siblings);
+        if matches!(self.padding, Padding::LengthBound) {
+            let length = self.compression.length(set_size);
+            node = self.compression.compress(depth, cap_index, &[&node, &length]);
+        }
+        node == cap[cap_index]
+    }
+}
+
+// Recomputes the node at height path.len() above the leaf at `index`.
+pub fn climb<C: Compression>(compression: &mut C, index: usize, leaf: &C::Node, path: &[C::Node]) -> C::Node {
+    let mut acc = leaf.to_owned();
+    for (lv, sibling) in path.iter().enumerate() {
+        let pos = index >> (lv + 1);
+        acc = if (index >> lv) & 1 == 0 {
+            compression.compress(lv, pos, &[&acc, sibling])
+        } else {
+            compression.compress(lv, pos, &[sibling, &acc])
+        };
+    }
+    acc
+}
+
+#[cfg(test)]
+mod merkle_tree_tests {
+    use super::*;
+    use crate::merkle_tree::merkle_tree_f2;
+    use sha2::{Digest, Sha256};
+
+    // non-commutative toy compression which records level and position
+    #[derive(Clone, Debug)]
+    struct Trace;
+
+    impl Compression for Trace {
+        type Node = String;
+
+        fn compress(&mut self, level: usize, position: usize, input: &[&String; 2]) -> String {
+            format!("({}.{}:{},{})", level, position, input[0], input[1])
+        }
+
+        fn zero(&self) -> String {
+            "0".to_string()
+        }
+
+        fn length(&self, len: usize) -> String {
+            format!("#{}", len)
+        }
+    }
+
+    fn paddings() -> Vec<Padding<String>> {
+        vec![
+            Padding::Legacy,
+            Padding::Zero,
+            Padding::EmptySubtree("e".to_string()),
+            Padding::Promote,
+            Padding::LengthBound,
+        ]
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        for padding in paddings() {
+            let mut mt = MerkleTree::from_compression(Trace, padding);
+            for set_size in 1..40 {
+                let set: Vec<String> = (0..set_size).map(|i| i.to_string()).collect();
+                let root = mt.accumulate(&set);
+                for threads in 1..9 {
+                    assert_eq!(mt.accumulate_parallel(&set, threads), root);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn caps_follow_padding() {
+        for padding in paddings() {
+            let mut mt = MerkleTree::from_compression(Trace, padding.clone());
+            for set_size in 1..12usize {
+                if padding == Padding::Promote && !set_size.is_power_of_two() {
+                    continue;
+                }
+                let set: Vec<String> = (0..set_size).map(|i| i.to_string()).collect();
+                let root = mt.accumulate(&set);
+                let depth = mt.depth(set_size).unwrap();
+                assert_eq!(mt.build_with_cap(&set, 0).cap(), &[root]);
+
+                for cap_height in 0..=depth {
+                    let tree = mt.build_with_cap(&set, cap_height);
+                    assert_eq!(tree.cap().len(), 1 << cap_height);
+                    for (i, leaf) in set.iter().enumerate() {
+                        let proof = tree.prove(i);
+                        assert!(mt.verify_with_cap(tree.cap(), set_size, i, leaf, &proof));
+                        assert!(!mt.verify_with_cap(tree.cap(), set_size + 8, i, leaf, &proof));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cap_proofs_have_full_length() {
+        let mut mt = MerkleTree::from_compression(Trace, Padding::default());
+        let set: Vec<String> = (0..8).map(|i| i.to_string()).collect();
+        let tree = mt.build_with_cap(&set, 1);
+        let cap = tree.cap().to_vec();
+        // a cap node is no leaf of an empty proof
+        let empty = MerkleProof { siblings: vec![] };
+        assert!(!mt.verify_with_cap(&cap, 8, 0, &cap[0], &empty));
+        let mut short = tree.prove(0);
+        let top = short.siblings.pop().unwrap();
+        let node = mt.climb(0, &set[0], &short.siblings);
+        let proof = MerkleProof { siblings: vec![top] };
+        assert!(!mt.verify_with_cap(&cap, 8, 0, &node, &proof));
+    }
+
+    #[test]
+    #[should_panic]
+    fn promoted_caps_need_powers_of_two() {
+        let mut mt = MerkleTree::from_compression(Trace, Padding::Promote);
+        let set: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        mt.build_with_cap(&set, 0);
+    }
+
+    #[test]
+    fn stored_trees_match_accumulate() {
+        for padding in [Padding::Legacy, Padding::Zero, Padding::Promote] {
+            let mut mt = MerkleTree::from_compression(Trace, padding.clone());
+            for set_size in [1usize, 2, 5, 8] {
+                if padding == Padding::Promote && (set_size == 1 || !set_size.is_power_of_two()) {
+                    continue;
+                }
+                let set: Vec<String> = (0..set_size).map(|i| i.to_string()).collect();
+                assert_eq!(mt.build_stored(&set).root().unwrap(), mt.accumulate(&set));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn stored_trees_reject_promoted_sets() {
+        let mt = MerkleTree::from_compression(Trace, Padding::Promote);
+        let set: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        mt.build_stored(&set);
+    }
+
+    #[test]
+    fn parallel_digest_tree() {
+        let mut mt = merkle_tree_f2::MerkleTree::<Sha256>::new();
+        let set: Vec<_> = (0..1000u32).map(|i| Sha256::digest(i.to_le_bytes())).collect();
+        let root = mt.accumulate(&set);
+        assert_eq!(mt.accumulate_parallel(&set, 4), root);
+        assert_eq!(mt.accumulate_parallel(&set, 7), root);
+    }
+}
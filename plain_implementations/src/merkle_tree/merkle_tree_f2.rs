@@ -3,69 +3,68 @@ use sha2::{
     Digest,
 };
 
+use super::merkle_tree::{self, Compression};
+use super::padding::Padding;
+
 #[derive(Clone, Debug)]
-pub struct MerkleTree<F: Digest + FixedOutputReset + Clone> {
-    hasher: F,
+pub struct DigestCompression<D: Digest + FixedOutputReset + Clone> {
+    hasher: D,
 }
 
-impl<F: Digest + FixedOutputReset + Clone> Default for MerkleTree<F> {
+impl<D: Digest + FixedOutputReset + Clone> Default for DigestCompression<D> {
     fn default() -> Self {
-        MerkleTree { hasher: F::new() }
+        Self::new()
     }
 }
 
-impl<F: Digest + FixedOutputReset + Clone> MerkleTree<F> {
+impl<D: Digest + FixedOutputReset + Clone> DigestCompression<D> {
     pub fn new() -> Self {
-        MerkleTree { hasher: F::new() }
+        DigestCompression { hasher: D::new() }
     }
+}
 
-    fn round_up_pow_n(input: usize, n: usize) -> usize {
-        debug_assert!(n >= 1);
-        let mut res = 1;
-        // try powers, starting from n
-        loop {
-            res *= n;
-            if res >= input {
-                break;
-            }
-        }
-        res
-    }
+impl<D: Digest + FixedOutputReset + Clone> Compression for DigestCompression<D> {
+    type Node = Output<D>;
 
-    fn compress(&mut self, input: &[&Output<F>; 2]) -> Output<F> {
-        <F as Digest>::update(&mut self.hasher, input[0]);
-        <F as Digest>::update(&mut self.hasher, input[1]);
+    fn compress(&mut self, _level: usize, _position: usize, input: &[&Output<D>; 2]) -> Output<D> {
+        <D as Digest>::update(&mut self.hasher, input[0]);
+        <D as Digest>::update(&mut self.hasher, input[1]);
         self.hasher.finalize_reset()
     }
 
-    pub fn accumulate(&mut self, set: &[Output<F>]) -> Output<F> {
-        let set_size = set.len();
-        let mut bound = Self::round_up_pow_n(set_size, 2);
-        loop {
-            if bound >= 2 {
-                break;
-            }
-            bound *= 2;
-        }
-        let mut nodes: Vec<Output<F>> = Vec::with_capacity(bound);
-        for s in set {
-            nodes.push(s.to_owned());
-        }
-        // pad
-        for _ in nodes.len()..bound {
-            nodes.push(nodes[set_size - 1].to_owned());
-        }
+    fn zero(&self) -> Output<D> {
+        Output::<D>::default()
+    }
+
+    // big-endian length in the last 8 bytes of an otherwise zero digest
+    fn length(&self, len: usize) -> Output<D> {
+        let mut node = Output::<D>::default();
+        let bytes = (len as u64).to_be_bytes();
+        let start = node.len().saturating_sub(bytes.len());
+        let skip = bytes.len() - (node.len() - start);
+        node[start..].copy_from_slice(&bytes[skip..]);
+        node
+    }
+}
+
+pub type MerkleTree<F> = merkle_tree::MerkleTree<DigestCompression<F>>;
+
+impl<F: Digest + FixedOutputReset + Clone> Default for MerkleTree<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Digest + FixedOutputReset + Clone> MerkleTree<F> {
+    pub fn new() -> Self {
+        Self::with_padding(Padding::default())
+    }
+
+    pub fn new_legacy() -> Self {
+        Self::with_padding(Padding::Legacy)
+    }
 
-        while nodes.len() > 1 {
-            let new_len = nodes.len() / 2;
-            let mut new_nodes: Vec<Output<F>> = Vec::with_capacity(new_len);
-            for i in (0..nodes.len()).step_by(2) {
-                let inp = [&nodes[i], &nodes[i + 1]];
-                let dig = self.compress(&inp);
-                new_nodes.push(dig);
-            }
-            nodes = new_nodes;
-        }
-        nodes[0].to_owned()
+    pub fn with_padding(padding: Padding<Output<F>>) -> Self {
+        Self::from_compression(DigestCompression::new(), padding)
     }
 }
@@ -1,65 +1,247 @@
 use ark_ff::PrimeField;
 use std::marker::PhantomData;
 
+use super::merkle_tree::{self, Compression};
+use super::padding::Padding;
+
+pub use super::merkle_tree::{CappedMerkleTree, MerkleProof};
+
 pub trait MerkleTreeHash<F: PrimeField> {
-    fn compress(&self, input: &[&F]) -> F;
+    // `tag` is placed into the capacity element
+    fn compress_tagged(&self, input: &[&F], tag: &F) -> F;
+
+    fn compress(&self, input: &[&F]) -> F {
+        self.compress_tagged(input, &F::zero())
+    }
+}
+
+// Hash of a variable number of elements, used by the trees for leaves and
+// other inputs which are not a pair of nodes.
+pub trait SpongeHash<F: PrimeField> {
+    fn hash(&self, input: &[F]) -> F;
+
+    // `tag` is placed into the capacity element instead of the input length
+    fn hash_tagged(&self, input: &[F], tag: &F) -> F;
+}
+
+// What is bound into the capacity element of every compression. The tag of a
+// node at `level` is 2^b + 2 + 2^8 * level, plus 2^64 * (position + 1) if
+// positions are tagged, and leaves are hashed with tag 2^b + 1. Bit b is set in
+// every tag, which keeps them apart from the input lengths the crate sponge
+// places into its capacity element. The encoding is injective for fields of
+// more than 128 bits. Legacy uses the untagged compression [a, b, 0] and does
+// not hash leaves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tagging {
+    Legacy,
+    #[default]
+    Level,
+    LevelAndPosition,
+}
+
+const LEAF_TAG: u128 = 1;
+const NODE_TAG: u128 = 2;
+
+// b = min(62, floor(log2(p)) - 2), one below the chunk domain of
+// poseidon2_tree_hash
+pub fn tag_bit<F: PrimeField>() -> u32 {
+    (F::MODULUS_BIT_SIZE - 3).min(62)
+}
+
+// 2^b + tag
+pub fn domain_tag<F: PrimeField>(tag: u128) -> F {
+    let bit = tag_bit::<F>();
+    assert!(tag < 1 << bit, "tag out of range");
+    F::from((1 << bit) + tag)
+}
+
+pub fn node_tag<F: PrimeField>(level: usize, position: Option<usize>) -> F {
+    let tag = domain_tag(NODE_TAG + ((level as u128) << 8));
+    match position {
+        None => tag,
+        Some(position) => {
+            assert!(F::MODULUS_BIT_SIZE > 128, "position tags need a field of more than 128 bits");
+            tag + F::from((position as u128 + 1) << 64)
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct MerkleTree<F: PrimeField, P: MerkleTreeHash<F>> {
+pub struct FpCompression<F: PrimeField, P: MerkleTreeHash<F>> {
     perm: P,
+    tagging: Tagging,
     field: PhantomData<F>,
 }
 
-impl<F: PrimeField, P: MerkleTreeHash<F>> MerkleTree<F, P> {
+impl<F: PrimeField, P: MerkleTreeHash<F>> FpCompression<F, P> {
     pub fn new(perm: P) -> Self {
-        MerkleTree {
+        Self::with_tagging(perm, Tagging::default())
+    }
+
+    pub fn new_legacy(perm: P) -> Self {
+        Self::with_tagging(perm, Tagging::Legacy)
+    }
+
+    pub fn with_tagging(perm: P, tagging: Tagging) -> Self {
+        assert!(
+            tagging != Tagging::LevelAndPosition || F::MODULUS_BIT_SIZE > 128,
+            "position tags need a field of more than 128 bits"
+        );
+        FpCompression {
             perm,
+            tagging,
             field: PhantomData,
         }
     }
 
-    fn round_up_pow_n(input: usize, n: usize) -> usize {
-        debug_assert!(n >= 1);
-        let mut res = 1;
-        // try powers, starting from n
-        loop {
-            res *= n;
-            if res >= input {
-                break;
-            }
-        }
-        res
+    pub fn perm(&self) -> &P {
+        &self.perm
     }
 
-    pub fn accumulate(&mut self, set: &[F]) -> F {
-        let set_size = set.len();
-        let mut bound = Self::round_up_pow_n(set_size, 2);
-        loop {
-            if bound >= 2 {
-                break;
-            }
-            bound *= 2;
-        }
-        let mut nodes: Vec<F> = Vec::with_capacity(bound);
-        for s in set {
-            nodes.push(s.to_owned());
+    pub fn tagging(&self) -> Tagging {
+        self.tagging
+    }
+
+    pub fn node_tag(&self, level: usize, position: usize) -> F {
+        match self.tagging {
+            Tagging::Legacy => F::zero(),
+            Tagging::Level => node_tag(level, None),
+            Tagging::LevelAndPosition => node_tag(level, Some(position)),
         }
-        // pad
-        for _ in nodes.len()..bound {
-            nodes.push(nodes[set_size - 1].to_owned());
+    }
+
+    pub fn hash_leaf(&self, value: &F) -> F {
+        match self.tagging {
+            Tagging::Legacy => value.to_owned(),
+            _ => self.perm.compress_tagged(&[value, &F::zero()], &domain_tag(LEAF_TAG)),
         }
+    }
+}
 
-        while nodes.len() > 1 {
-            let new_len = nodes.len() / 2;
-            let mut new_nodes: Vec<F> = Vec::with_capacity(new_len);
-            for i in (0..nodes.len()).step_by(2) {
-                let inp = [&nodes[i], &nodes[i + 1]];
-                let dig = self.perm.compress(&inp);
-                new_nodes.push(dig);
+impl<F: PrimeField, P: MerkleTreeHash<F>> Compression for FpCompression<F, P> {
+    type Node = F;
+
+    fn compress(&mut self, level: usize, position: usize, input: &[&F; 2]) -> F {
+        let tag = self.node_tag(level, position);
+        self.perm.compress_tagged(input, &tag)
+    }
+
+    fn zero(&self) -> F {
+        F::zero()
+    }
+
+    fn length(&self, len: usize) -> F {
+        F::from(len as u64)
+    }
+}
+
+pub type MerkleTree<F, P> = merkle_tree::MerkleTree<FpCompression<F, P>>;
+
+impl<F: PrimeField, P: MerkleTreeHash<F>> MerkleTree<F, P> {
+    pub fn new(perm: P) -> Self {
+        Self::with_padding(perm, Padding::default())
+    }
+
+    // untagged compression and legacy padding
+    pub fn new_legacy(perm: P) -> Self {
+        Self::from_compression(FpCompression::new_legacy(perm), Padding::Legacy)
+    }
+
+    pub fn with_padding(perm: P, padding: Padding<F>) -> Self {
+        Self::with_tagging(perm, padding, Tagging::default())
+    }
+
+    pub fn with_tagging(perm: P, padding: Padding<F>, tagging: Tagging) -> Self {
+        Self::from_compression(FpCompression::with_tagging(perm, tagging), padding)
+    }
+
+    pub fn hash_leaves(&self, values: &[F]) -> Vec<F> {
+        values.iter().map(|v| self.compression().hash_leaf(v)).collect()
+    }
+}
+
+#[cfg(test)]
+mod merkle_tree_fp_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+
+    type Scalar = FpBN256;
+
+    #[test]
+    fn cap_proofs() {
+        let mut mt = MerkleTree::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS));
+        let set: Vec<Scalar> = (0..16).map(|_| random_scalar()).collect();
+        let root = mt.accumulate(&set);
+
+        for cap_height in 0..=4 {
+            let tree = mt.build_with_cap(&set, cap_height);
+            assert_eq!(tree.cap().len(), 1 << cap_height);
+            let mut nodes = tree.cap().to_vec();
+            for lv in 4 - cap_height..4 {
+                nodes = (0..nodes.len() / 2)
+                    .map(|pos| mt.compression_mut().compress(lv, pos, &[&nodes[2 * pos], &nodes[2 * pos + 1]]))
+                    .collect();
+            }
+            assert_eq!(nodes[0], root);
+            for (i, leaf) in set.iter().enumerate() {
+                let proof = tree.prove(i);
+                assert_eq!(proof.siblings.len(), 4 - cap_height);
+                assert!(mt.verify_with_cap(tree.cap(), 16, i, leaf, &proof));
+                assert!(!mt.verify_with_cap(tree.cap(), 16, i ^ 1, leaf, &proof));
             }
-            nodes = new_nodes;
         }
-        nodes[0].to_owned()
+    }
+
+    #[test]
+    fn tagging() {
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let set: Vec<Scalar> = (0..4).map(|_| random_scalar()).collect();
+
+        let mut legacy = MerkleTree::new_legacy(perm.clone());
+        let untagged = perm.compress(&[&perm.compress(&[&set[0], &set[1]]), &perm.compress(&[&set[2], &set[3]])]);
+        assert_eq!(legacy.accumulate(&set), untagged);
+        assert_eq!(legacy.hash_leaves(&set), set);
+
+        // a subtree root can not be passed off as a leaf of a smaller tree
+        let mut mt = MerkleTree::new(perm.clone());
+        let root = mt.accumulate(&set);
+        let left = mt.accumulate(&set[..2]);
+        let right = mt.accumulate(&set[2..]);
+        assert_ne!(mt.accumulate(&[left, right]), root);
+        let legacy_subtrees = [legacy.accumulate(&set[..2]), legacy.accumulate(&set[2..])];
+        assert_eq!(legacy.accumulate(&legacy_subtrees), untagged);
+
+        let mut positional = MerkleTree::with_tagging(perm, Padding::default(), Tagging::LevelAndPosition);
+        let swapped = vec![set[2], set[3], set[0], set[1]];
+        let c = positional.compression().to_owned();
+        assert_ne!(c.node_tag(0, 0), c.node_tag(0, 1));
+        assert_ne!(c.node_tag(0, 1), c.node_tag(1, 0));
+        assert_ne!(positional.accumulate(&set), root);
+        assert_ne!(positional.accumulate(&set), positional.accumulate(&swapped));
+
+        // leaves live in their own domain
+        let leaves = mt.hash_leaves(&set);
+        assert_ne!(leaves, set);
+        assert_ne!(leaves[0], mt.compression_mut().compress(0, 0, &[&set[0], &Scalar::from(0u64)]));
+    }
+
+    #[test]
+    fn tags_apart_from_sponge() {
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let mut mt = MerkleTree::new(perm.clone());
+        let set: Vec<Scalar> = (0..2).map(|_| random_scalar()).collect();
+        assert_ne!(perm.hash(&set), mt.compression_mut().compress(0, 0, &[&set[0], &set[1]]));
+        assert_ne!(perm.hash(&set[..1]), mt.compression().hash_leaf(&set[0]));
+        assert_ne!(perm.hash(&set), mt.compression_mut().compress(1, 0, &[&set[0], &set[1]]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn position_tags_need_large_fields() {
+        use crate::fields::goldilocks::FpGoldiLocks;
+        use crate::poseidon2::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        MerkleTree::<FpGoldiLocks, _>::with_tagging(perm, Padding::default(), Tagging::LevelAndPosition);
     }
 }
@@ -0,0 +1,180 @@
+use ark_ff::PrimeField;
+use ark_std::rand::Rng;
+
+use super::merkle_tree::{climb, MerkleProof};
+use super::merkle_tree_fp::{FpCompression, MerkleTreeHash, SpongeHash};
+use super::stored_merkle_tree::StoredMerkleTree;
+use crate::poseidon2::poseidon2::Poseidon2;
+
+// Source of the per-leaf salts of a hiding tree.
+pub trait SaltSource<F: PrimeField> {
+    fn salt(&mut self, index: usize) -> F;
+}
+
+// Uniformly random salts.
+#[derive(Clone, Debug)]
+pub struct RngSalts<R: Rng> {
+    rng: R,
+}
+
+impl<R: Rng> RngSalts<R> {
+    pub fn new(rng: R) -> Self {
+        RngSalts { rng }
+    }
+}
+
+impl<F: PrimeField, R: Rng> SaltSource<F> for RngSalts<R> {
+    fn salt(&mut self, _index: usize) -> F {
+        F::rand(&mut self.rng)
+    }
+}
+
+// Deterministic salts hash(key, index), e.g., to recompute the salts of a
+// commitment from a secret key instead of storing them.
+#[derive(Clone, Debug)]
+pub struct PrfSalts<F: PrimeField, P: SpongeHash<F> = Poseidon2<F>> {
+    perm: P,
+    key: F,
+}
+
+impl<F: PrimeField, P: SpongeHash<F>> PrfSalts<F, P> {
+    pub fn new(perm: P, key: F) -> Self {
+        PrfSalts { perm, key }
+    }
+}
+
+impl<F: PrimeField, P: SpongeHash<F>> SaltSource<F> for PrfSalts<F, P> {
+    fn salt(&mut self, index: usize) -> F {
+        self.perm.hash(&[self.key, F::from(index as u64)])
+    }
+}
+
+pub fn commit_leaf<F: PrimeField, P: SpongeHash<F>>(perm: &P, value: &F, salt: &F) -> F {
+    perm.hash(&[*value, *salt])
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HidingOpening<F: PrimeField> {
+    pub index: usize,
+    pub value: F,
+    pub salt: F,
+    pub path: MerkleProof<F>,
+}
+
+// Every value enters the tree as hash(value, salt), so the sibling hashes in an
+// opening do not reveal anything about unopened values. The tree is padded with
+// zero leaves to the next power of two.
+#[derive(Clone, Debug)]
+pub struct HidingMerkleTree<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone = Poseidon2<F>> {
+    perm: P,
+    tree: StoredMerkleTree<FpCompression<F, P>>,
+    values: Vec<F>,
+    salts: Vec<F>,
+}
+
+impl<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone> HidingMerkleTree<F, P> {
+    pub fn commit<S: SaltSource<F>>(perm: P, values: &[F], salts: &mut S) -> Self {
+        let salts: Vec<F> = (0..values.len()).map(|i| salts.salt(i)).collect();
+        let mut leaves: Vec<F> = values
+            .iter()
+            .zip(salts.iter())
+            .map(|(value, salt)| commit_leaf(&perm, value, salt))
+            .collect();
+        leaves.resize(values.len().next_power_of_two().max(2), F::zero());
+        let tree = StoredMerkleTree::new(FpCompression::new(perm.clone()), leaves);
+        HidingMerkleTree {
+            perm,
+            tree,
+            values: values.to_owned(),
+            salts,
+        }
+    }
+
+    pub fn root(&self) -> F {
+        self.tree.root().unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn open(&self, index: usize) -> HidingOpening<F> {
+        assert!(index < self.values.len());
+        HidingOpening {
+            index,
+            value: self.values[index],
+            salt: self.salts[index],
+            path: self.tree.prove(index).unwrap(),
+        }
+    }
+
+    pub fn verify(&mut self, root: &F, opening: &HidingOpening<F>) -> bool {
+        let leaf = commit_leaf(&self.perm, &opening.value, &opening.salt);
+        self.tree.verify(root, opening.index, &leaf, &opening.path)
+    }
+}
+
+pub fn verify_hiding_opening<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> + Clone>(
+    perm: &P,
+    root: &F,
+    opening: &HidingOpening<F>,
+) -> bool {
+    let mut compression = FpCompression::new(perm.clone());
+    let leaf = commit_leaf(perm, &opening.value, &opening.salt);
+    opening.index >> opening.path.siblings.len() == 0
+        && climb(&mut compression, opening.index, &leaf, &opening.path.siblings) == *root
+}
+
+#[cfg(test)]
+mod merkle_tree_hiding_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+
+    type Scalar = FpBN256;
+
+    fn perm() -> Poseidon2<Scalar> {
+        Poseidon2::new(&POSEIDON2_BN256_PARAMS)
+    }
+
+    #[test]
+    fn openings() {
+        let values: Vec<Scalar> = (0..6).map(|_| random_scalar()).collect();
+        let mut tree = HidingMerkleTree::commit(perm(), &values, &mut RngSalts::new(ark_std::rand::thread_rng()));
+        let root = tree.root();
+        for (i, value) in values.iter().enumerate() {
+            let opening = tree.open(i);
+            assert_eq!(opening.value, *value);
+            assert!(tree.verify(&root, &opening));
+            assert!(verify_hiding_opening(&perm(), &root, &opening));
+
+            let mut tampered = opening.clone();
+            tampered.salt += Scalar::from(1u64);
+            assert!(!verify_hiding_opening(&perm(), &root, &tampered));
+        }
+    }
+
+    #[test]
+    fn salts_are_reproducible() {
+        let values: Vec<Scalar> = (0..4).map(|i| Scalar::from(i as u64)).collect();
+        let key = random_scalar();
+
+        let a = HidingMerkleTree::commit(perm(), &values, &mut PrfSalts::new(perm(), key));
+        let b = HidingMerkleTree::commit(perm(), &values, &mut PrfSalts::new(perm(), key));
+        assert_eq!(a.root(), b.root());
+        assert_eq!(a.open(2).salt, perm().hash(&[key, Scalar::from(2u64)]));
+
+        let c = HidingMerkleTree::commit(perm(), &values, &mut RngSalts::new(ark_std::test_rng()));
+        let d = HidingMerkleTree::commit(perm(), &values, &mut RngSalts::new(ark_std::test_rng()));
+        assert_eq!(c.root(), d.root());
+        assert_ne!(a.root(), c.root());
+
+        // the same values under different salts give unrelated roots
+        let e = HidingMerkleTree::commit(perm(), &values, &mut PrfSalts::new(perm(), key + Scalar::from(1u64)));
+        assert_ne!(a.root(), e.root());
+    }
+}
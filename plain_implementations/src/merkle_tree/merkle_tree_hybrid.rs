@@ -0,0 +1,227 @@
+use ark_ff::PrimeField;
+use sha2::{
+    digest::{FixedOutputReset, Output},
+    Digest,
+};
+
+use super::merkle_tree::Compression;
+use super::merkle_tree_f2::DigestCompression;
+use super::merkle_tree_fp::{FpCompression, MerkleTreeHash, SpongeHash};
+use crate::fields::utils::pack_bytes;
+use crate::poseidon2::poseidon2::Poseidon2;
+
+// Boundary conversion: the digest is packed into field elements (see
+// fields::utils::pack_bytes) and absorbed by the sponge, yielding one field
+// element per boundary node. This is a collision-resistant hash rather than an
+// injective map, so the field has to have at least 254 bits for the single
+// element nodes of the upper region to keep the security of the digest.
+pub const MIN_FIELD_BITS: u32 = 254;
+
+pub fn digest_to_field<D: Digest, F: PrimeField, P: SpongeHash<F>>(perm: &P, digest: &Output<D>) -> F {
+    assert!(F::MODULUS_BIT_SIZE >= MIN_FIELD_BITS, "field too small for single element nodes");
+    perm.hash(&pack_bytes::<F>(digest))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HybridProof<D: Digest, F: PrimeField> {
+    pub digest_siblings: Vec<Output<D>>,
+    pub field_siblings: Vec<F>,
+}
+
+// The lowest `digest_levels` levels are compressed with D as in merkle_tree_f2,
+// all levels above the boundary with the level-tagged Poseidon2 compression as
+// in merkle_tree_fp. Levels are counted from the leaves, so the boundary nodes
+// are compressed at level `digest_levels`.
+#[derive(Clone, Debug)]
+pub struct HybridMerkleTree<
+    D: Digest + FixedOutputReset + Clone,
+    F: PrimeField,
+    P: MerkleTreeHash<F> + SpongeHash<F> = Poseidon2<F>,
+> {
+    digest: DigestCompression<D>,
+    field: FpCompression<F, P>,
+    digest_levels: usize,
+    // levels[0] are the leaves, the last level are the boundary digests
+    lower: Vec<Vec<Output<D>>>,
+    // upper[0] are the converted boundary nodes, the last level is the root
+    upper: Vec<Vec<F>>,
+}
+
+impl<D: Digest + FixedOutputReset + Clone, F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F>> HybridMerkleTree<D, F, P> {
+    // The number of leaves has to be a power of two of at least 2^digest_levels.
+    pub fn new(perm: P, digest_levels: usize, leaves: &[Output<D>]) -> Self {
+        assert!(F::MODULUS_BIT_SIZE >= MIN_FIELD_BITS, "field too small for single element nodes");
+        assert!(leaves.len().is_power_of_two());
+        assert!(leaves.len() >= 1 << digest_levels);
+
+        let mut digest = DigestCompression::<D>::new();
+        let mut lower = vec![leaves.to_owned()];
+        for lv in 0..digest_levels {
+            let new_nodes = lower[lv]
+                .chunks(2)
+                .enumerate()
+                .map(|(pos, pair)| digest.compress(lv, pos, &[&pair[0], &pair[1]]))
+                .collect();
+            lower.push(new_nodes);
+        }
+
+        let boundary = lower[digest_levels]
+            .iter()
+            .map(|d| digest_to_field::<D, F, P>(&perm, d))
+            .collect();
+        let mut field = FpCompression::new(perm);
+        let mut upper: Vec<Vec<F>> = vec![boundary];
+        while upper.last().unwrap().len() > 1 {
+            let lv = digest_levels + upper.len() - 1;
+            let new_nodes = upper
+                .last()
+                .unwrap()
+                .chunks(2)
+                .enumerate()
+                .map(|(pos, pair)| field.compress(lv, pos, &[&pair[0], &pair[1]]))
+                .collect();
+            upper.push(new_nodes);
+        }
+
+        HybridMerkleTree {
+            digest,
+            field,
+            digest_levels,
+            lower,
+            upper,
+        }
+    }
+
+    pub fn root(&self) -> F {
+        self.upper.last().unwrap()[0]
+    }
+
+    pub fn digest_levels(&self) -> usize {
+        self.digest_levels
+    }
+
+    pub fn boundary(&self) -> &[F] {
+        &self.upper[0]
+    }
+
+    pub fn prove(&self, index: usize) -> HybridProof<D, F> {
+        assert!(index < self.lower[0].len());
+        let digest_siblings = (0..self.digest_levels)
+            .map(|lv| self.lower[lv][(index >> lv) ^ 1].to_owned())
+            .collect();
+        let upper_index = index >> self.digest_levels;
+        let field_siblings = (0..self.upper.len() - 1)
+            .map(|lv| self.upper[lv][(upper_index >> lv) ^ 1])
+            .collect();
+        HybridProof {
+            digest_siblings,
+            field_siblings,
+        }
+    }
+
+    pub fn verify(&mut self, root: &F, index: usize, leaf: &Output<D>, proof: &HybridProof<D, F>) -> bool {
+        if proof.digest_siblings.len() != self.digest_levels
+            || proof.field_siblings.len() != self.upper.len() - 1
+            || index >= self.lower[0].len()
+        {
+            return false;
+        }
+        let mut acc = leaf.to_owned();
+        for (lv, sibling) in proof.digest_siblings.iter().enumerate() {
+            let pos = index >> (lv + 1);
+            acc = if (index >> lv) & 1 == 0 {
+                self.digest.compress(lv, pos, &[&acc, sibling])
+            } else {
+                self.digest.compress(lv, pos, &[sibling, &acc])
+            };
+        }
+
+        let upper_index = index >> self.digest_levels;
+        let mut acc = digest_to_field::<D, F, P>(self.field.perm(), &acc);
+        for (lv, sibling) in proof.field_siblings.iter().enumerate() {
+            let (level, pos) = (self.digest_levels + lv, upper_index >> (lv + 1));
+            acc = if (upper_index >> lv) & 1 == 0 {
+                self.field.compress(level, pos, &[&acc, sibling])
+            } else {
+                self.field.compress(level, pos, &[sibling, &acc])
+            };
+        }
+        acc == *root
+    }
+}
+
+#[cfg(test)]
+mod merkle_tree_hybrid_tests {
+    use super::*;
+    use crate::fields::bn256::FpBN256;
+    use crate::merkle_tree::{
+        merkle_tree_f2,
+        merkle_tree_fp::{node_tag, MerkleTreeHash},
+    };
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+    use blake2::Blake2s256;
+    use sha2::Sha256;
+
+    type Scalar = FpBN256;
+
+    #[test]
+    fn boundary_packing() {
+        let digest = Sha256::digest(b"boundary");
+        let packed = pack_bytes::<Scalar>(&digest);
+        assert_eq!(packed.len(), 2);
+        assert_eq!(packed[1], Scalar::from(digest[31] as u64));
+    }
+
+    #[test]
+    #[should_panic]
+    fn small_fields() {
+        use crate::fields::goldilocks::FpGoldiLocks;
+        use crate::poseidon2::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+        let leaves: Vec<_> = (0..4u32).map(|i| Sha256::digest(i.to_le_bytes())).collect();
+        HybridMerkleTree::<Sha256, FpGoldiLocks>::new(Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS), 1, &leaves);
+    }
+
+    #[test]
+    fn regions_match_plain_trees() {
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let leaves: Vec<_> = (0..32u32).map(|i| Sha256::digest(i.to_le_bytes())).collect();
+        let tree = HybridMerkleTree::<Sha256, Scalar>::new(perm.clone(), 3, &leaves);
+
+        let mut f2 = merkle_tree_f2::MerkleTree::<Sha256>::new();
+        let boundary: Vec<Scalar> = leaves
+            .chunks(8)
+            .map(|c| digest_to_field::<Sha256, Scalar, _>(&perm, &f2.accumulate(c)))
+            .collect();
+        assert_eq!(tree.boundary(), &boundary[..]);
+
+        // the first upper level is compressed at level 3
+        let tagged = |lv: usize, l: &Scalar, r: &Scalar| perm.compress_tagged(&[l, r], &node_tag(lv, None));
+        let left = tagged(3, &boundary[0], &boundary[1]);
+        let right = tagged(3, &boundary[2], &boundary[3]);
+        assert_eq!(tree.root(), tagged(4, &left, &right));
+    }
+
+    #[test]
+    fn proofs_span_both_regions() {
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let leaves: Vec<_> = (0..16u32).map(|i| Blake2s256::digest(i.to_le_bytes())).collect();
+        for digest_levels in 0..=4 {
+            let mut tree = HybridMerkleTree::<Blake2s256, Scalar>::new(perm.clone(), digest_levels, &leaves);
+            let root = tree.root();
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = tree.prove(i);
+                assert_eq!(proof.digest_siblings.len(), digest_levels);
+                assert_eq!(proof.field_siblings.len(), 4 - digest_levels);
+                assert!(tree.verify(&root, i, leaf, &proof));
+                assert!(!tree.verify(&root, i, &leaves[(i + 1) % 16], &proof));
+            }
+
+            // a truncated proof leads to an inner node, not to the root
+            let mut proof = tree.prove(0);
+            if proof.field_siblings.pop().is_some() {
+                let inner = tree.upper[proof.field_siblings.len()][0];
+                assert!(!tree.verify(&inner, 0, &leaves[0], &proof));
+            }
+        }
+    }
+}
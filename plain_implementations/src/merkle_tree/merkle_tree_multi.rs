@@ -0,0 +1,176 @@
+use ark_ff::PrimeField;
+
+use super::merkle_tree::{self, Compression};
+use super::padding::Padding;
+use crate::poseidon2::poseidon2::Poseidon2;
+use crate::poseidon2::poseidon2_compression::{CompressionMode, Poseidon2Compression};
+
+// Nodes are digests of N field elements, e.g., 4 Goldilocks or 8 BabyBear
+// elements for about 128 bits of collision resistance. Two digests are
+// compressed by permuting x = [left, right, 0, ..., 0] and reducing the result
+// to N elements according to the compression mode, by default the feed-forward
+// (P(x) + x)[0..N]. This requires a permutation of width t >= 2N, such as the
+// Goldilocks t = 8 and BabyBear t = 16 instances. Truncation needs zero padding
+// to stay one-way and is rejected for t = 2N.
+#[derive(Clone, Debug)]
+pub struct ArrayCompression<F: PrimeField, const N: usize> {
+    compression: Poseidon2Compression<F>,
+}
+
+impl<F: PrimeField, const N: usize> ArrayCompression<F, N> {
+    pub fn new(perm: Poseidon2<F>) -> Self {
+        Self::with_mode(perm, CompressionMode::FeedForward)
+    }
+
+    pub fn with_mode(perm: Poseidon2<F>, mode: CompressionMode) -> Self {
+        assert!(N >= 1);
+        assert!(perm.get_t() >= 2 * N, "permutation too narrow for the digest");
+        assert!(
+            mode != CompressionMode::Truncate || perm.get_t() > 2 * N,
+            "truncation of a full state is invertible"
+        );
+        ArrayCompression {
+            compression: Poseidon2Compression::new(perm, mode),
+        }
+    }
+
+    pub fn perm(&self) -> &Poseidon2<F> {
+        self.compression.perm()
+    }
+
+    // Sponge hash of an arbitrary number of elements into a digest, with a
+    // capacity of N elements and rate t - N.
+    pub fn hash_leaf(&self, values: &[F]) -> [F; N] {
+        let mut out = [F::zero(); N];
+        out.copy_from_slice(&self.perm().hash_many(values, N));
+        out
+    }
+}
+
+impl<F: PrimeField, const N: usize> Compression for ArrayCompression<F, N> {
+    type Node = [F; N];
+
+    fn compress(&mut self, _level: usize, _position: usize, input: &[&[F; N]; 2]) -> [F; N] {
+        let mut state = input[0].to_vec();
+        state.extend_from_slice(input[1]);
+        let mut out = [F::zero(); N];
+        out.copy_from_slice(&self.compression.compress_to(&state, N));
+        out
+    }
+
+    fn zero(&self) -> [F; N] {
+        [F::zero(); N]
+    }
+
+    fn length(&self, len: usize) -> [F; N] {
+        let mut node = [F::zero(); N];
+        node[0] = F::from(len as u64);
+        node
+    }
+}
+
+pub type MerkleTree<F, const N: usize> = merkle_tree::MerkleTree<ArrayCompression<F, N>>;
+
+impl<F: PrimeField, const N: usize> MerkleTree<F, N> {
+    pub fn new(perm: Poseidon2<F>) -> Self {
+        Self::with_padding(perm, Padding::default())
+    }
+
+    pub fn new_legacy(perm: Poseidon2<F>) -> Self {
+        Self::with_padding(perm, Padding::Legacy)
+    }
+
+    pub fn with_padding(perm: Poseidon2<F>, padding: Padding<[F; N]>) -> Self {
+        Self::from_compression(ArrayCompression::new(perm), padding)
+    }
+
+    pub fn with_mode(perm: Poseidon2<F>, mode: CompressionMode) -> Self {
+        Self::from_compression(ArrayCompression::with_mode(perm, mode), Padding::default())
+    }
+}
+
+#[cfg(test)]
+mod merkle_tree_multi_tests {
+    use super::*;
+    use crate::fields::{babybear::FpBabyBear, goldilocks::FpGoldiLocks, utils::random_scalar};
+    use crate::merkle_tree::merkle_tree_fp::MerkleTreeHash;
+    use crate::poseidon2::poseidon2_instance_babybear::POSEIDON2_BABYBEAR_16_PARAMS;
+    use crate::poseidon2::poseidon2_instance_goldilocks::{POSEIDON2_GOLDILOCKS_12_PARAMS, POSEIDON2_GOLDILOCKS_8_PARAMS};
+
+    fn random_digest<F: PrimeField, const N: usize>() -> [F; N] {
+        let mut out = [F::zero(); N];
+        out.iter_mut().for_each(|x| *x = random_scalar());
+        out
+    }
+
+    #[test]
+    fn goldilocks_4() {
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        let mut mt = MerkleTree::<FpGoldiLocks, 4>::new(perm.clone());
+        let set: Vec<[FpGoldiLocks; 4]> = (0..4).map(|_| random_digest()).collect();
+
+        let compress = |l: &[FpGoldiLocks], r: &[FpGoldiLocks]| {
+            let mut state = l.to_vec();
+            state.extend_from_slice(r);
+            let p = perm.permutation(&state);
+            (0..4).map(|i| p[i] + state[i]).collect::<Vec<_>>()
+        };
+        let l = compress(&set[0], &set[1]);
+        let r = compress(&set[2], &set[3]);
+        let root = mt.accumulate(&set);
+        assert_eq!(root.to_vec(), compress(&l, &r));
+
+        let tree = mt.build_with_cap(&set, 0);
+        for (i, leaf) in set.iter().enumerate() {
+            let proof = tree.prove(i);
+            assert_eq!(mt.climb(i, leaf, &proof.siblings), root);
+        }
+    }
+
+    #[test]
+    fn babybear_8() {
+        let perm = Poseidon2::new(&POSEIDON2_BABYBEAR_16_PARAMS);
+        let mut mt = MerkleTree::<FpBabyBear, 8>::with_padding(perm, Padding::Zero);
+        let values: Vec<FpBabyBear> = (0..20).map(|_| random_scalar()).collect();
+        let set: Vec<[FpBabyBear; 8]> = values.chunks(3).map(|c| mt.compression().hash_leaf(c)).collect();
+        assert_eq!(set.len(), 7);
+        // 8 rate and 8 capacity elements
+        let perm = mt.compression().perm();
+        assert_eq!(set[0].to_vec(), perm.hash_many_with_rate(&values[..3], 8, 8));
+        let mut state = values[..3].to_vec();
+        state.resize(16, FpBabyBear::from(0u64));
+        state[15] = FpBabyBear::from(3u64);
+        assert_eq!(set[0].to_vec(), perm.permutation(&state)[..8].to_vec());
+        let root = mt.accumulate(&set);
+        assert_eq!(mt.accumulate_parallel(&set, 3), root);
+
+        let mut stored = mt.build_stored(&set);
+        assert_eq!(stored.num_leaves(), 8);
+        let proof = stored.prove(6).unwrap();
+        let new_root = stored.update(6, random_digest()).unwrap();
+        assert!(!stored.verify(&new_root, 6, &set[6], &proof));
+    }
+
+    #[test]
+    #[should_panic]
+    fn narrow_permutation() {
+        ArrayCompression::<FpGoldiLocks, 8>::new(Poseidon2::new(&POSEIDON2_GOLDILOCKS_12_PARAMS));
+    }
+
+    #[test]
+    #[should_panic]
+    fn truncation_without_padding() {
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        ArrayCompression::<FpGoldiLocks, 4>::with_mode(perm, CompressionMode::Truncate);
+    }
+
+    #[test]
+    fn wide_single_element_compression() {
+        // the single element compression no longer requires t = 3
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        let (a, b) = (random_scalar::<FpGoldiLocks>(), random_scalar());
+        let mut state = vec![a, b];
+        state.resize(8, FpGoldiLocks::from(0u64));
+        assert_eq!(perm.compress(&[&a, &b]), perm.permutation(&state)[0]);
+    }
+}
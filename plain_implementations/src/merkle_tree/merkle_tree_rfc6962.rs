@@ -0,0 +1,436 @@
+use sha2::{
+    digest::{FixedOutputReset, Output},
+    Digest,
+};
+
+use super::merkle_tree::{self, Compression};
+use super::merkle_tree_f2::DigestCompression;
+use super::padding::Padding;
+
+// Merkle hash trees as specified in RFC 6962, Section 2.1. Leaves and inner
+// nodes are hashed with distinct one-byte prefixes, and a tree over n leaves
+// is split at the largest power of two smaller than n. This is the shape of
+// the generic tree with `Padding::Promote`, whose empty tree is the hash of
+// the empty string.
+#[derive(Clone, Debug)]
+pub struct Rfc6962Compression<D: Digest + FixedOutputReset + Clone> {
+    hasher: D,
+}
+
+impl<D: Digest + FixedOutputReset + Clone> Default for Rfc6962Compression<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest + FixedOutputReset + Clone> Rfc6962Compression<D> {
+    pub fn new() -> Self {
+        Rfc6962Compression { hasher: D::new() }
+    }
+
+    pub fn leaf(&mut self, data: &[u8]) -> Output<D> {
+        Digest::update(&mut self.hasher, [0x00]);
+        Digest::update(&mut self.hasher, data);
+        self.hasher.finalize_reset()
+    }
+
+    pub fn node(&mut self, left: &Output<D>, right: &Output<D>) -> Output<D> {
+        Digest::update(&mut self.hasher, [0x01]);
+        Digest::update(&mut self.hasher, left);
+        Digest::update(&mut self.hasher, right);
+        self.hasher.finalize_reset()
+    }
+}
+
+impl<D: Digest + FixedOutputReset + Clone> Compression for Rfc6962Compression<D> {
+    type Node = Output<D>;
+
+    fn compress(&mut self, _level: usize, _position: usize, input: &[&Output<D>; 2]) -> Output<D> {
+        self.node(input[0], input[1])
+    }
+
+    fn zero(&self) -> Output<D> {
+        D::digest([])
+    }
+
+    fn length(&self, len: usize) -> Output<D> {
+        DigestCompression::<D>::new().length(len)
+    }
+}
+
+pub type MerkleTree<D> = merkle_tree::MerkleTree<Rfc6962Compression<D>>;
+
+impl<D: Digest + FixedOutputReset + Clone> Default for MerkleTree<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest + FixedOutputReset + Clone> MerkleTree<D> {
+    pub fn new() -> Self {
+        Self::from_compression(Rfc6962Compression::new(), Padding::Promote)
+    }
+}
+
+// largest power of two strictly smaller than n > 1
+fn split(n: usize) -> usize {
+    debug_assert!(n > 1);
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+// An append-only log which can produce roots, audit paths and consistency
+// proofs for every past tree size. As in the MMR, levels[h][i] is the root of
+// the perfect subtree over the leaves [i * 2^h, (i + 1) * 2^h), so appending
+// never changes a stored node.
+#[derive(Clone, Debug)]
+pub struct Rfc6962Tree<D: Digest + FixedOutputReset + Clone> {
+    compression: Rfc6962Compression<D>,
+    levels: Vec<Vec<Output<D>>>,
+}
+
+impl<D: Digest + FixedOutputReset + Clone> Default for Rfc6962Tree<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest + FixedOutputReset + Clone> Rfc6962Tree<D> {
+    pub fn new() -> Self {
+        Rfc6962Tree {
+            compression: Rfc6962Compression::new(),
+            levels: vec![Vec::new()],
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn leaf_hashes(&self) -> &[Output<D>] {
+        &self.levels[0]
+    }
+
+    // Returns the index of the new leaf.
+    pub fn append(&mut self, data: &[u8]) -> usize {
+        let hash = self.compression.leaf(data);
+        self.append_leaf_hash(hash)
+    }
+
+    pub fn append_leaf_hash(&mut self, hash: Output<D>) -> usize {
+        let index = self.size();
+        self.levels[0].push(hash);
+
+        let mut h = 0;
+        while self.levels[h].len() & 1 == 0 {
+            let len = self.levels[h].len();
+            let node = self.compression.node(&self.levels[h][len - 2], &self.levels[h][len - 1]);
+            if self.levels.len() == h + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[h + 1].push(node);
+            h += 1;
+        }
+        index
+    }
+
+    // MTH of the leaves [start, start + len). Every range reached by the RFC
+    // recursion starts at a multiple of its largest perfect subtree, so it is
+    // covered by the stored subtrees given by the binary digits of len.
+    fn range_root(&mut self, start: usize, len: usize) -> Output<D> {
+        if len == 0 {
+            return self.compression.zero();
+        }
+        let mut subtrees = Vec::with_capacity(len.count_ones() as usize);
+        let mut offset = start;
+        for h in (0..usize::BITS as usize).rev() {
+            if (len >> h) & 1 == 1 {
+                debug_assert_eq!(offset % (1 << h), 0);
+                subtrees.push(self.levels[h][offset >> h].to_owned());
+                offset += 1 << h;
+            }
+        }
+        let mut acc = subtrees.pop().unwrap();
+        for node in subtrees.iter().rev() {
+            acc = self.compression.node(node, &acc);
+        }
+        acc
+    }
+
+    pub fn root(&mut self) -> Output<D> {
+        self.range_root(0, self.size())
+    }
+
+    // root of the tree over the first `size` leaves
+    pub fn root_at(&mut self, size: usize) -> Output<D> {
+        assert!(size <= self.size());
+        self.range_root(0, size)
+    }
+
+    // PATH(index, D[size]) of RFC 6962, Section 2.1.1
+    pub fn audit_path(&mut self, index: usize, size: usize) -> Vec<Output<D>> {
+        assert!(size <= self.size());
+        assert!(index < size);
+        let mut path = Vec::new();
+        self.path_of(index, 0, size, &mut path);
+        path
+    }
+
+    // path of `index` within the subtree over the leaves [start, start + n)
+    fn path_of(&mut self, index: usize, start: usize, n: usize, path: &mut Vec<Output<D>>) {
+        if n <= 1 {
+            return;
+        }
+        let k = split(n);
+        if index < k {
+            self.path_of(index, start, k, path);
+            path.push(self.range_root(start + k, n - k));
+        } else {
+            self.path_of(index - k, start + k, n - k, path);
+            path.push(self.range_root(start, k));
+        }
+    }
+
+    // PROOF(m, D[n]) of RFC 6962, Section 2.1.2, for 0 < m <= n
+    pub fn consistency_proof(&mut self, m: usize, n: usize) -> Vec<Output<D>> {
+        assert!(n <= self.size());
+        assert!(0 < m && m <= n);
+        let mut proof = Vec::new();
+        self.subproof(m, 0, n, true, &mut proof);
+        proof
+    }
+
+    // SUBPROOF(m, D[start:start + n], b) of RFC 6962, Section 2.1.2
+    fn subproof(&mut self, m: usize, start: usize, n: usize, complete: bool, proof: &mut Vec<Output<D>>) {
+        if m == n {
+            if !complete {
+                proof.push(self.range_root(start, n));
+            }
+            return;
+        }
+        let k = split(n);
+        if m <= k {
+            self.subproof(m, start, k, complete, proof);
+            proof.push(self.range_root(start + k, n - k));
+        } else {
+            self.subproof(m - k, start + k, n - k, false, proof);
+            proof.push(self.range_root(start, k));
+        }
+    }
+}
+
+// Verification of an audit path as in RFC 9162, Section 2.1.3.2.
+pub fn verify_inclusion<D: Digest + FixedOutputReset + Clone>(
+    index: usize,
+    size: usize,
+    leaf: &Output<D>,
+    path: &[Output<D>],
+    root: &Output<D>,
+) -> bool {
+    if index >= size {
+        return false;
+    }
+    let mut compression = Rfc6962Compression::<D>::new();
+    let mut fn_ = index;
+    let mut sn = size - 1;
+    let mut r = leaf.to_owned();
+    for p in path {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = compression.node(p, &r);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = compression.node(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && r == *root
+}
+
+// Verification of a consistency proof as in RFC 9162, Section 2.1.4.2.
+pub fn verify_consistency<D: Digest + FixedOutputReset + Clone>(
+    m: usize,
+    n: usize,
+    first_root: &Output<D>,
+    second_root: &Output<D>,
+    proof: &[Output<D>],
+) -> bool {
+    if m == 0 || m > n {
+        return false;
+    }
+    if m == n {
+        return proof.is_empty() && first_root == second_root;
+    }
+    if proof.is_empty() {
+        return false;
+    }
+
+    let mut compression = Rfc6962Compression::<D>::new();
+    let mut path = Vec::with_capacity(proof.len() + 1);
+    if m.is_power_of_two() {
+        path.push(first_root.to_owned());
+    }
+    path.extend_from_slice(proof);
+
+    let mut fn_ = m - 1;
+    let mut sn = n - 1;
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    let mut fr = path[0].to_owned();
+    let mut sr = path[0].to_owned();
+    for c in path[1..].iter() {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = compression.node(c, &fr);
+            sr = compression.node(c, &sr);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            sr = compression.node(&sr, c);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && fr == *first_root && sr == *second_root
+}
+
+#[cfg(test)]
+mod merkle_tree_rfc6962_tests {
+    use super::*;
+    use sha2::Sha256;
+
+    type H = Output<Sha256>;
+
+    fn from_hex(s: &str) -> H {
+        let bytes = hex::decode(s).unwrap();
+        H::clone_from_slice(&bytes)
+    }
+
+    // the 7 leaf example tree of RFC 6962, Section 2.1.3
+    fn example() -> (Rfc6962Tree<Sha256>, Vec<H>) {
+        let mut tree = Rfc6962Tree::<Sha256>::new();
+        for i in 0..7u8 {
+            tree.append(&[i]);
+        }
+        let l = tree.leaf_hashes().to_owned();
+        let mut compression = Rfc6962Compression::<Sha256>::new();
+        let mut node = |a: &H, b: &H| compression.node(a, b);
+        let g = node(&l[0], &l[1]);
+        let h = node(&l[2], &l[3]);
+        let i = node(&l[4], &l[5]);
+        let k = node(&g, &h);
+        let ll = node(&i, &l[6]);
+        let root = node(&k, &ll);
+        // a b c d e f j g h i k l root
+        let mut nodes = l;
+        nodes.extend([g, h, i, k, ll, root]);
+        (tree, nodes)
+    }
+
+    #[test]
+    fn rfc_example_structure() {
+        let (mut tree, n) = example();
+        let mut compression = Rfc6962Compression::<Sha256>::new();
+        let (a, c, d, f, j) = (&n[0], &n[2], &n[3], &n[5], &n[6]);
+        let (g, h, i, k, l, root) = (&n[7], &n[8], &n[9], &n[10], &n[11], &n[12]);
+        assert_eq!(tree.root(), *root);
+        assert_eq!(tree.root_at(3), compression.node(g, c));
+        assert_eq!(tree.root_at(4), *k);
+        assert_eq!(tree.root_at(6), compression.node(k, i));
+
+        assert_eq!(tree.audit_path(1, 7), vec![*a, *h, *l]);
+        assert_eq!(tree.audit_path(3, 7), vec![*c, *g, *l]);
+        assert_eq!(tree.audit_path(4, 7), vec![*f, *j, *k]);
+        assert_eq!(tree.audit_path(6, 7), vec![*i, *k]);
+
+        assert_eq!(tree.consistency_proof(3, 7), vec![*c, *d, *g, *l]);
+        assert_eq!(tree.consistency_proof(4, 7), vec![*l]);
+        assert_eq!(tree.consistency_proof(6, 7), vec![*i, *j, *k]);
+    }
+
+    #[test]
+    fn reference_roots() {
+        // leaves and roots of the certificate transparency reference tests
+        let leaves = [
+            "",
+            "00",
+            "10",
+            "2021",
+            "3031",
+            "40414243",
+            "5051525354555657",
+            "606162636465666768696a6b6c6d6e6f",
+        ];
+        let roots = [
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+            "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+            "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+            "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+            "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+        ];
+        let mut tree = Rfc6962Tree::<Sha256>::new();
+        assert_eq!(
+            tree.root(),
+            from_hex("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+        for (leaf, root) in leaves.iter().zip(roots.iter()) {
+            tree.append(&hex::decode(leaf).unwrap());
+            assert_eq!(tree.root(), from_hex(root));
+        }
+    }
+
+    #[test]
+    fn proofs_verify() {
+        let mut tree = Rfc6962Tree::<Sha256>::new();
+        for i in 0..33u32 {
+            tree.append(&i.to_le_bytes());
+        }
+        for n in 1..=33 {
+            let root = tree.root_at(n);
+            for index in 0..n {
+                let leaf = tree.leaf_hashes()[index];
+                let path = tree.audit_path(index, n);
+                assert!(verify_inclusion::<Sha256>(index, n, &leaf, &path, &root));
+                assert!(!verify_inclusion::<Sha256>(index ^ 1, n, &leaf, &path, &root));
+                assert!(n == 1 || !verify_inclusion::<Sha256>(index, n, &root, &path, &leaf));
+            }
+            for m in 1..=n {
+                let first = tree.root_at(m);
+                let proof = tree.consistency_proof(m, n);
+                assert!(verify_consistency::<Sha256>(m, n, &first, &root, &proof));
+                if m < n {
+                    assert!(!verify_consistency::<Sha256>(m, n, &root, &first, &proof));
+                    assert!(!verify_consistency::<Sha256>(m, n, &first, &root, &proof[1..]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_promoted_tree() {
+        let mut tree = Rfc6962Tree::<Sha256>::new();
+        let mut mt = MerkleTree::<Sha256>::new();
+        for i in 0..40u32 {
+            assert_eq!(mt.accumulate(tree.leaf_hashes()), tree.root());
+            tree.append(&i.to_le_bytes());
+        }
+        let leaves = tree.leaf_hashes().to_owned();
+        assert_eq!(mt.accumulate_parallel(&leaves, 4), tree.root());
+        let path = tree.audit_path(5, 32);
+        assert_eq!(mt.climb(5, &leaves[5], &path), tree.root_at(32));
+    }
+}
@@ -1,64 +1,90 @@
 use jubjub::Base;
 
+use super::merkle_tree::{self, Compression};
+use super::merkle_tree_fp::{self, node_tag};
+use super::padding::Padding;
+use crate::fields::{
+    conversions::{jubjub_to_ark, ark_to_jubjub},
+    bls12::FpBLS12,
+};
+
 type F = Base;
 
 pub trait MerkleTreeHash {
     fn compress(&self, level: usize, input: &[&F; 2]) -> F;
 }
 
+// Every hash over the same prime in ark-ff representation, e.g., Poseidon and
+// Poseidon2, with the level bound into the capacity element as in the Fp tree.
+impl<P: merkle_tree_fp::MerkleTreeHash<FpBLS12>> MerkleTreeHash for P {
+    fn compress(&self, level: usize, input: &[&F; 2]) -> F {
+        let input = [jubjub_to_ark(input[0]), jubjub_to_ark(input[1])];
+        let out = self.compress_tagged(&[&input[0], &input[1]], &node_tag(level, None));
+        ark_to_jubjub(&out)
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct MerkleTree<P: MerkleTreeHash> {
+pub struct SaplingCompression<P: MerkleTreeHash> {
     perm: P,
 }
 
+impl<P: MerkleTreeHash> SaplingCompression<P> {
+    pub fn new(perm: P) -> Self {
+        SaplingCompression { perm }
+    }
+
+    pub fn perm(&self) -> &P {
+        &self.perm
+    }
+}
+
+impl<P: MerkleTreeHash> Compression for SaplingCompression<P> {
+    type Node = F;
+
+    fn compress(&mut self, level: usize, _position: usize, input: &[&F; 2]) -> F {
+        self.perm.compress(level, input)
+    }
+
+    fn zero(&self) -> F {
+        F::zero()
+    }
+
+    fn length(&self, len: usize) -> F {
+        F::from(len as u64)
+    }
+}
+
+pub type MerkleTree<P> = merkle_tree::MerkleTree<SaplingCompression<P>>;
+
 impl<P: MerkleTreeHash> MerkleTree<P> {
     pub fn new(perm: P) -> Self {
-        MerkleTree { perm }
+        Self::with_padding(perm, Padding::default())
+    }
+
+    pub fn new_legacy(perm: P) -> Self {
+        Self::with_padding(perm, Padding::Legacy)
     }
 
-    fn round_up_pow_n(input: usize, n: usize) -> usize {
-        debug_assert!(n >= 1);
-        let mut res = 1;
-        // try powers, starting from n
-        loop {
-            res *= n;
-            if res >= input {
-                break;
-            }
-        }
-        res
+    pub fn with_padding(perm: P, padding: Padding<F>) -> Self {
+        Self::from_compression(SaplingCompression::new(perm), padding)
     }
+}
+
+#[cfg(test)]
+mod merkle_tree_sapling_tests {
+    use super::*;
+    use crate::fields::utils::random_scalar;
+    use crate::poseidon::poseidon::Poseidon;
+    use crate::poseidon::poseidon_instance_bls12::POSEIDON_BLS_3_PARAMS;
+
+    #[test]
+    fn matches_fp_tree() {
+        let set: Vec<FpBLS12> = (0..11).map(|_| random_scalar()).collect();
+        let converted: Vec<F> = set.iter().map(ark_to_jubjub).collect();
 
-    pub fn accumulate(&mut self, set: &[F]) -> F {
-        let set_size = set.len();
-        let mut bound = Self::round_up_pow_n(set_size, 2);
-        loop {
-            if bound >= 2 {
-                break;
-            }
-            bound *= 2;
-        }
-        let mut nodes: Vec<F> = Vec::with_capacity(bound);
-        for s in set {
-            nodes.push(s.to_owned());
-        }
-        // pad
-        for _ in nodes.len()..bound {
-            nodes.push(nodes[set_size - 1].to_owned());
-        }
-
-        let mut lv = 0;
-        while nodes.len() > 1 {
-            let new_len = nodes.len() / 2;
-            let mut new_nodes: Vec<F> = Vec::with_capacity(new_len);
-            for i in (0..nodes.len()).step_by(2) {
-                let inp = [&nodes[i], &nodes[i + 1]];
-                let dig = self.perm.compress(lv, &inp);
-                new_nodes.push(dig);
-            }
-            lv += 1;
-            nodes = new_nodes;
-        }
-        nodes[0].to_owned()
+        let mut mt = MerkleTree::new(Poseidon::new(&POSEIDON_BLS_3_PARAMS));
+        let mut fp = merkle_tree_fp::MerkleTree::new(Poseidon::new(&POSEIDON_BLS_3_PARAMS));
+        assert_eq!(mt.accumulate(&converted), ark_to_jubjub(&fp.accumulate(&set)));
     }
 }
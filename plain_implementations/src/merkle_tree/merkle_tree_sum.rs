@@ -0,0 +1,191 @@
+use ark_ff::PrimeField;
+use std::marker::PhantomData;
+
+use super::merkle_tree::{Compression, MerkleProof};
+use super::merkle_tree_fp::SpongeHash;
+use super::stored_merkle_tree::StoredMerkleTree;
+use crate::poseidon2::poseidon2::Poseidon2;
+
+// Every node commits to a hash and to the sum of all balances below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SumNode<F: PrimeField> {
+    pub hash: F,
+    pub sum: u64,
+}
+
+// Inner nodes are the sponge hash of (left_hash, left_sum, right_hash, right_sum)
+// together with left_sum + right_sum. Sums are unsigned, so no balance can
+// cancel out another one, and have to stay below max_sum, so that they are
+// embedded into the field without reduction.
+#[derive(Clone, Debug)]
+pub struct SumCompression<F: PrimeField, P: SpongeHash<F> = Poseidon2<F>> {
+    perm: P,
+    field: PhantomData<F>,
+}
+
+impl<F: PrimeField, P: SpongeHash<F>> SumCompression<F, P> {
+    pub fn new(perm: P) -> Self {
+        SumCompression {
+            perm,
+            field: PhantomData,
+        }
+    }
+
+    pub fn max_sum() -> u64 {
+        if F::MODULUS_BIT_SIZE > 64 {
+            u64::MAX
+        } else {
+            (1u64 << (F::MODULUS_BIT_SIZE - 1)) - 1
+        }
+    }
+
+    pub fn leaf(&self, id: &F, balance: u64) -> SumNode<F> {
+        assert!(balance <= Self::max_sum(), "balance out of range");
+        SumNode {
+            hash: self.perm.hash(&[*id, F::from(balance)]),
+            sum: balance,
+        }
+    }
+
+    // None if the sum leaves the range
+    pub fn try_compress(&self, left: &SumNode<F>, right: &SumNode<F>) -> Option<SumNode<F>> {
+        let sum = left.sum.checked_add(right.sum)?;
+        if sum > Self::max_sum() {
+            return None;
+        }
+        let hash = self
+            .perm
+            .hash(&[left.hash, F::from(left.sum), right.hash, F::from(right.sum)]);
+        Some(SumNode { hash, sum })
+    }
+}
+
+impl<F: PrimeField, P: SpongeHash<F>> Compression for SumCompression<F, P> {
+    type Node = SumNode<F>;
+
+    fn compress(&mut self, _level: usize, _position: usize, input: &[&SumNode<F>; 2]) -> SumNode<F> {
+        self.try_compress(input[0], input[1]).expect("sum overflow")
+    }
+
+    fn zero(&self) -> SumNode<F> {
+        SumNode {
+            hash: F::zero(),
+            sum: 0,
+        }
+    }
+
+    fn length(&self, len: usize) -> SumNode<F> {
+        SumNode {
+            hash: F::from(len as u64),
+            sum: 0,
+        }
+    }
+}
+
+pub type MerkleSumTree<F, P = Poseidon2<F>> = StoredMerkleTree<SumCompression<F, P>>;
+
+// One leaf per (id, balance), padded with empty leaves to the next power of two.
+// The root sum is the total of all balances.
+pub fn build_sum_tree<F: PrimeField, P: SpongeHash<F>>(perm: P, accounts: &[(F, u64)]) -> MerkleSumTree<F, P> {
+    let compression = SumCompression::new(perm);
+    let mut leaves: Vec<SumNode<F>> = accounts
+        .iter()
+        .map(|(id, balance)| compression.leaf(id, *balance))
+        .collect();
+    leaves.resize(accounts.len().next_power_of_two().max(2), compression.zero());
+    StoredMerkleTree::new(compression, leaves)
+}
+
+// Checks that the account (id, balance) is included at `index` in the tree with
+// the given root, whose sum is the published total. Every intermediate sum is
+// range checked, so a proof cannot make the total wrap around.
+pub fn verify_sum_inclusion<F: PrimeField, P: SpongeHash<F>>(
+    compression: &SumCompression<F, P>,
+    root: &SumNode<F>,
+    index: usize,
+    id: &F,
+    balance: u64,
+    proof: &MerkleProof<SumNode<F>>,
+) -> bool {
+    if balance > SumCompression::<F, P>::max_sum() || index >> proof.siblings.len() != 0 {
+        return false;
+    }
+    let mut acc = compression.leaf(id, balance);
+    for (lv, sibling) in proof.siblings.iter().enumerate() {
+        let next = if (index >> lv) & 1 == 0 {
+            compression.try_compress(&acc, sibling)
+        } else {
+            compression.try_compress(sibling, &acc)
+        };
+        acc = match next {
+            Some(node) => node,
+            None => return false,
+        };
+    }
+    acc == *root
+}
+
+#[cfg(test)]
+mod merkle_tree_sum_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, goldilocks::FpGoldiLocks, utils::random_scalar};
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+
+    type Scalar = FpBN256;
+
+    fn compression() -> SumCompression<Scalar> {
+        SumCompression::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS))
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        let accounts: Vec<(Scalar, u64)> = (0..5).map(|i| (random_scalar(), 100 * i + 7)).collect();
+        let tree = build_sum_tree(Poseidon2::new(&POSEIDON2_BN256_PARAMS), &accounts);
+        let root = tree.root().unwrap();
+        assert_eq!(root.sum, 7 + 107 + 207 + 307 + 407);
+        assert_eq!(tree.num_leaves(), 8);
+
+        let c = compression();
+        for (i, (id, balance)) in accounts.iter().enumerate() {
+            let proof = tree.prove(i).unwrap();
+            assert!(verify_sum_inclusion(&c, &root, i, id, *balance, &proof));
+            assert!(!verify_sum_inclusion(&c, &root, i, id, balance + 1, &proof));
+            assert!(!verify_sum_inclusion(&c, &root, i ^ 1, id, *balance, &proof));
+
+            // claiming a smaller total changes the root
+            let mut smaller = root;
+            smaller.sum -= 1;
+            assert!(!verify_sum_inclusion(&c, &smaller, i, id, *balance, &proof));
+        }
+    }
+
+    #[test]
+    fn overflowing_siblings_are_rejected() {
+        let c = compression();
+        let id = random_scalar();
+        let leaf = c.leaf(&id, 10);
+        let sibling = c.leaf(&random_scalar(), u64::MAX - 5);
+        let proof = MerkleProof {
+            siblings: vec![sibling],
+        };
+        let root = SumNode {
+            hash: random_scalar(),
+            sum: 4,
+        };
+        assert!(!verify_sum_inclusion(&c, &root, 0, &id, 10, &proof));
+        assert_eq!(c.try_compress(&leaf, &sibling), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overflowing_tree_panics() {
+        let accounts = vec![(random_scalar(), u64::MAX), (random_scalar(), 1)];
+        build_sum_tree::<Scalar, _>(Poseidon2::new(&POSEIDON2_BN256_PARAMS), &accounts);
+    }
+
+    #[test]
+    fn small_field_range() {
+        assert_eq!(SumCompression::<Scalar>::max_sum(), u64::MAX);
+        assert_eq!(SumCompression::<FpGoldiLocks>::max_sum(), (1 << 63) - 1);
+    }
+}
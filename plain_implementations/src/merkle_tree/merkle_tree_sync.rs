@@ -0,0 +1,234 @@
+use std::io;
+use std::ops::Range;
+
+use super::merkle_tree::Compression;
+use super::storage::NodeStorage;
+use super::stored_merkle_tree::StoredMerkleTree;
+
+// Requests the nodes at the given indices of one level of the remote tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeQuery {
+    pub level: usize,
+    pub indices: Vec<usize>,
+}
+
+// The requested nodes, in query order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeReply<N> {
+    pub level: usize,
+    pub nodes: Vec<N>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeDiff<N> {
+    // maximal runs of differing leaves, sorted
+    pub ranges: Vec<Range<usize>>,
+    // the remote values of all differing leaves, sorted by index
+    pub leaves: Vec<(usize, N)>,
+    // number of nodes received over all rounds
+    pub nodes_received: usize,
+}
+
+// Remote side of the protocol. Queries outside of the tree are rejected.
+pub fn answer<C: Compression, S: NodeStorage<C::Node>>(
+    tree: &StoredMerkleTree<C, S>,
+    query: &NodeQuery,
+) -> io::Result<NodeReply<C::Node>> {
+    if query.level > tree.depth() || query.indices.iter().any(|i| *i >> (tree.depth() - query.level) != 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "query out of range"));
+    }
+    Ok(NodeReply {
+        level: query.level,
+        nodes: query
+            .indices
+            .iter()
+            .map(|i| tree.node(query.level, *i))
+            .collect::<io::Result<_>>()?,
+    })
+}
+
+// Local side of the protocol. Starting at the root, every round requests the
+// children of all mismatching nodes of the previous round, so both trees are
+// compared top-down one level per round and matching subtrees are never
+// visited. Both trees need to have the same depth.
+#[derive(Debug)]
+pub struct DiffSession<'a, C: Compression, S: NodeStorage<C::Node>> {
+    tree: &'a StoredMerkleTree<C, S>,
+    query: Option<NodeQuery>,
+    leaves: Vec<(usize, C::Node)>,
+    nodes_received: usize,
+}
+
+impl<'a, C: Compression, S: NodeStorage<C::Node>> DiffSession<'a, C, S> {
+    pub fn new(tree: &'a StoredMerkleTree<C, S>) -> Self {
+        DiffSession {
+            query: Some(NodeQuery {
+                level: tree.depth(),
+                indices: vec![0],
+            }),
+            tree,
+            leaves: Vec::new(),
+            nodes_received: 0,
+        }
+    }
+
+    // None once the comparison is complete
+    pub fn next_query(&self) -> Option<&NodeQuery> {
+        self.query.as_ref()
+    }
+
+    // A reply not matching the pending query is rejected, the query stays
+    // pending.
+    pub fn receive(&mut self, reply: NodeReply<C::Node>) -> io::Result<()> {
+        let query = self.query.as_ref().expect("no query pending");
+        if reply.level != query.level || reply.nodes.len() != query.indices.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "reply does not match the query"));
+        }
+        let query = self.query.take().unwrap();
+        self.nodes_received += reply.nodes.len();
+
+        let level = query.level;
+        let mut mismatches = Vec::new();
+        for (index, node) in query.indices.into_iter().zip(reply.nodes) {
+            if self.tree.node(level, index)? != node {
+                mismatches.push((index, node));
+            }
+        }
+        if level == 0 {
+            self.leaves.extend(mismatches);
+            return Ok(());
+        }
+        let indices: Vec<usize> = mismatches
+            .into_iter()
+            .flat_map(|(index, _)| [2 * index, 2 * index + 1])
+            .collect();
+        if !indices.is_empty() {
+            self.query = Some(NodeQuery {
+                level: level - 1,
+                indices,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> TreeDiff<C::Node> {
+        assert!(self.query.is_none(), "comparison not complete");
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (index, _) in self.leaves.iter() {
+            match ranges.last_mut() {
+                Some(range) if range.end == *index => range.end += 1,
+                _ => ranges.push(*index..*index + 1),
+            }
+        }
+        TreeDiff {
+            ranges,
+            leaves: self.leaves,
+            nodes_received: self.nodes_received,
+        }
+    }
+}
+
+// Runs the protocol between two local trees.
+pub fn diff<C: Compression, S: NodeStorage<C::Node>, R: NodeStorage<C::Node>>(
+    local: &StoredMerkleTree<C, S>,
+    remote: &StoredMerkleTree<C, R>,
+) -> io::Result<TreeDiff<C::Node>> {
+    assert_eq!(local.depth(), remote.depth());
+    let mut session = DiffSession::new(local);
+    while let Some(query) = session.next_query() {
+        let reply = answer(remote, query)?;
+        session.receive(reply)?;
+    }
+    Ok(session.finish())
+}
+
+// Brings `local` to the state of `remote` and returns the applied diff.
+pub fn sync<C: Compression, S: NodeStorage<C::Node>, R: NodeStorage<C::Node>>(
+    local: &mut StoredMerkleTree<C, S>,
+    remote: &StoredMerkleTree<C, R>,
+) -> io::Result<TreeDiff<C::Node>> {
+    let diff = diff(local, remote)?;
+    local.batch_update(&diff.leaves)?;
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod merkle_tree_sync_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::merkle_tree::merkle_tree_fp::FpCompression;
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+
+    type Scalar = FpBN256;
+
+    fn tree(leaves: Vec<Scalar>) -> StoredMerkleTree<FpCompression<Scalar, Poseidon2<Scalar>>> {
+        StoredMerkleTree::new(FpCompression::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS)), leaves)
+    }
+
+    #[test]
+    fn identical_trees() {
+        let leaves: Vec<Scalar> = (0..16).map(|_| random_scalar()).collect();
+        let a = tree(leaves.clone());
+        let b = tree(leaves);
+        let d = diff(&a, &b).unwrap();
+        assert!(d.ranges.is_empty());
+        assert_eq!(d.nodes_received, 1);
+    }
+
+    #[test]
+    fn malformed_messages() {
+        let leaves: Vec<Scalar> = (0..16).map(|_| random_scalar()).collect();
+        let a = tree(leaves.clone());
+        let b = tree(leaves);
+        for (level, index) in [(5, 0), (4, 1), (2, 4), (0, 16)] {
+            let query = NodeQuery { level, indices: vec![0, index] };
+            assert_eq!(answer(&b, &query).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut session = DiffSession::new(&a);
+        let reply = answer(&b, session.next_query().unwrap()).unwrap();
+        let wrong_level = NodeReply { level: 3, nodes: reply.nodes.clone() };
+        assert!(session.receive(wrong_level).is_err());
+        let wrong_len = NodeReply { level: 4, nodes: vec![] };
+        assert!(session.receive(wrong_len).is_err());
+        session.receive(reply).unwrap();
+        assert!(session.finish().ranges.is_empty());
+    }
+
+    #[test]
+    fn finds_differing_ranges() {
+        let leaves: Vec<Scalar> = (0..64).map(|_| random_scalar()).collect();
+        let mut local = tree(leaves.clone());
+        let mut remote = tree(leaves);
+        let changed = [5, 6, 7, 8, 40, 63];
+        let updates: Vec<(usize, Scalar)> = changed.iter().map(|i| (*i, random_scalar())).collect();
+        remote.batch_update(&updates).unwrap();
+
+        let d = sync(&mut local, &remote).unwrap();
+        assert_eq!(d.ranges, vec![5..9, 40..41, 63..64]);
+        assert_eq!(d.leaves, updates);
+        assert!(d.nodes_received < 64);
+        assert_eq!(local.root().unwrap(), remote.root().unwrap());
+        assert!(diff(&local, &remote).unwrap().ranges.is_empty());
+    }
+
+    #[test]
+    fn step_by_step() {
+        let leaves: Vec<Scalar> = (0..8).map(|_| random_scalar()).collect();
+        let local = tree(leaves.clone());
+        let mut remote = tree(leaves);
+        remote.update(2, random_scalar()).unwrap();
+
+        let mut session = DiffSession::new(&local);
+        let mut levels = vec![];
+        while let Some(query) = session.next_query() {
+            // one mismatching node per level, both of its children are requested
+            assert_eq!(query.indices.len(), if query.level == 3 { 1 } else { 2 });
+            levels.push(query.level);
+            let reply = answer(&remote, query).unwrap();
+            session.receive(reply).unwrap();
+        }
+        assert_eq!(levels, vec![3, 2, 1, 0]);
+        assert_eq!(session.finish().ranges, vec![2..3]);
+    }
+}
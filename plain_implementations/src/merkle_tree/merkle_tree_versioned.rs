@@ -0,0 +1,260 @@
+use ark_ff::PrimeField;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::merkle_tree::{climb, Compression, MerkleProof};
+use super::merkle_tree_fp::{FpCompression, MerkleTreeHash};
+
+pub type VersionId = u64;
+
+#[derive(Debug)]
+enum Node<F: PrimeField> {
+    Leaf(F),
+    Inner { hash: F, left: Link<F>, right: Link<F> },
+}
+
+type Link<F> = Arc<Node<F>>;
+
+impl<F: PrimeField> Node<F> {
+    fn hash(&self) -> F {
+        match self {
+            Node::Leaf(hash) => *hash,
+            Node::Inner { hash, .. } => *hash,
+        }
+    }
+
+    fn children(&self) -> (&Link<F>, &Link<F>) {
+        match self {
+            Node::Leaf(_) => unreachable!(),
+            Node::Inner { left, right, .. } => (left, right),
+        }
+    }
+}
+
+// A persistent fixed-depth tree: an update copies the path from the leaf to the
+// root and shares all other subtrees with the previous version, so every
+// retained version costs O(log n) nodes per update. Version ids are increasing
+// and never reused, also not after a rollback.
+#[derive(Clone, Debug)]
+pub struct VersionedMerkleTree<F: PrimeField, P: MerkleTreeHash<F>> {
+    compression: FpCompression<F, P>,
+    depth: usize,
+    versions: BTreeMap<VersionId, Link<F>>,
+    current: VersionId,
+    next_id: VersionId,
+}
+
+impl<F: PrimeField, P: MerkleTreeHash<F>> VersionedMerkleTree<F, P> {
+    // Version 0 has 2^depth zero leaves, all empty subtrees of a level are shared.
+    pub fn new(perm: P, depth: usize) -> Self {
+        let mut compression = FpCompression::new(perm);
+        let mut empty = Arc::new(Node::Leaf(compression.zero()));
+        for lv in 0..depth {
+            let hash = compression.compress(lv, 0, &[&empty.hash(), &empty.hash()]);
+            empty = Arc::new(Node::Inner {
+                hash,
+                left: empty.clone(),
+                right: empty,
+            });
+        }
+        let mut versions = BTreeMap::new();
+        versions.insert(0, empty);
+        VersionedMerkleTree {
+            compression,
+            depth,
+            versions,
+            current: 0,
+            next_id: 1,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn current_version(&self) -> VersionId {
+        self.current
+    }
+
+    // retained versions, oldest first
+    pub fn versions(&self) -> Vec<VersionId> {
+        self.versions.keys().cloned().collect()
+    }
+
+    pub fn root(&self) -> F {
+        self.versions[&self.current].hash()
+    }
+
+    pub fn root_at(&self, version: VersionId) -> Option<F> {
+        self.versions.get(&version).map(|root| root.hash())
+    }
+
+    pub fn leaf_at(&self, version: VersionId, index: usize) -> Option<F> {
+        assert!(index >> self.depth == 0);
+        let mut node = self.versions.get(&version)?;
+        for lv in (0..self.depth).rev() {
+            let (left, right) = node.children();
+            node = if (index >> lv) & 1 == 0 { left } else { right };
+        }
+        Some(node.hash())
+    }
+
+    pub fn prove_at(&self, version: VersionId, index: usize) -> Option<MerkleProof<F>> {
+        assert!(index >> self.depth == 0);
+        let mut node = self.versions.get(&version)?;
+        let mut siblings = Vec::with_capacity(self.depth);
+        for lv in (0..self.depth).rev() {
+            let (left, right) = node.children();
+            let (next, sibling) = if (index >> lv) & 1 == 0 {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            siblings.push(sibling.hash());
+            node = next;
+        }
+        siblings.reverse();
+        Some(MerkleProof { siblings })
+    }
+
+    pub fn prove(&self, index: usize) -> MerkleProof<F> {
+        self.prove_at(self.current, index).unwrap()
+    }
+
+    pub fn verify(&mut self, root: &F, index: usize, leaf: &F, proof: &MerkleProof<F>) -> bool {
+        proof.siblings.len() == self.depth
+            && index >> self.depth == 0
+            && climb(&mut self.compression, index, leaf, &proof.siblings) == *root
+    }
+
+    // Copies the path to the leaf at `index` below `node` of height `level`.
+    fn set(&mut self, node: &Link<F>, level: usize, index: usize, value: F) -> Link<F> {
+        if level == 0 {
+            return Arc::new(Node::Leaf(value));
+        }
+        let (left, right) = node.children();
+        let (left, right) = if (index >> (level - 1)) & 1 == 0 {
+            (self.set(left, level - 1, index, value), right.clone())
+        } else {
+            (left.clone(), self.set(right, level - 1, index, value))
+        };
+        let hash = self
+            .compression
+            .compress(level - 1, index >> level, &[&left.hash(), &right.hash()]);
+        Arc::new(Node::Inner { hash, left, right })
+    }
+
+    // Applies the updates on top of the current version and stores the result
+    // as a new version, which becomes the current one.
+    pub fn batch_update(&mut self, updates: &[(usize, F)]) -> VersionId {
+        let mut root = self.versions[&self.current].clone();
+        for (index, value) in updates {
+            assert!(index >> self.depth == 0);
+            root = self.set(&root, self.depth, *index, *value);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.versions.insert(id, root);
+        self.current = id;
+        id
+    }
+
+    pub fn update(&mut self, index: usize, value: F) -> VersionId {
+        self.batch_update(&[(index, value)])
+    }
+
+    // Makes `version` the current one and discards all newer versions.
+    // Returns false if the version is not retained.
+    pub fn rollback(&mut self, version: VersionId) -> bool {
+        if !self.versions.contains_key(&version) {
+            return false;
+        }
+        self.versions.split_off(&(version + 1));
+        self.current = version;
+        true
+    }
+
+    // Discards all versions older than `horizon`. The current version is
+    // always retained.
+    pub fn prune(&mut self, horizon: VersionId) {
+        let horizon = horizon.min(self.current);
+        self.versions = self.versions.split_off(&horizon);
+    }
+}
+
+#[cfg(test)]
+mod merkle_tree_versioned_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::merkle_tree::stored_merkle_tree::StoredMerkleTree;
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+
+    type Scalar = FpBN256;
+
+    fn perm() -> Poseidon2<Scalar> {
+        Poseidon2::new(&POSEIDON2_BN256_PARAMS)
+    }
+
+    #[test]
+    fn versions_match_stored_tree() {
+        let mut tree = VersionedMerkleTree::new(perm(), 5);
+        let mut stored = StoredMerkleTree::with_depth(FpCompression::new(perm()), 5);
+        assert_eq!(tree.root(), stored.root().unwrap());
+
+        let mut roots = vec![tree.root()];
+        for i in 0..10 {
+            let updates = vec![(3 * i, random_scalar()), (31 - i, random_scalar())];
+            let id = tree.batch_update(&updates);
+            assert_eq!(id, i as VersionId + 1);
+            assert_eq!(tree.root(), stored.batch_update(&updates).unwrap());
+            roots.push(tree.root());
+        }
+
+        // older versions are still readable
+        for (version, root) in roots.iter().enumerate() {
+            let version = version as VersionId;
+            assert_eq!(tree.root_at(version), Some(*root));
+            for index in [0, 9, 27, 31] {
+                let leaf = tree.leaf_at(version, index).unwrap();
+                let proof = tree.prove_at(version, index).unwrap();
+                assert!(tree.verify(root, index, &leaf, &proof));
+            }
+        }
+        assert_eq!(tree.prove(9), stored.prove(9).unwrap());
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let mut tree = VersionedMerkleTree::new(perm(), 4);
+        let v1 = tree.update(0, random_scalar());
+        let v2 = tree.update(15, random_scalar());
+        let (l1, r1) = tree.versions[&v1].children();
+        let (l2, r2) = tree.versions[&v2].children();
+        assert!(Arc::ptr_eq(l1, l2));
+        assert!(!Arc::ptr_eq(r1, r2));
+    }
+
+    #[test]
+    fn rollback_and_prune() {
+        let mut tree = VersionedMerkleTree::new(perm(), 3);
+        let v1 = tree.update(1, random_scalar());
+        let root1 = tree.root();
+        let v2 = tree.update(2, random_scalar());
+        tree.update(3, random_scalar());
+
+        assert!(tree.rollback(v1));
+        assert_eq!(tree.root(), root1);
+        assert_eq!(tree.versions(), vec![0, v1]);
+        assert_eq!(tree.root_at(v2), None);
+        assert!(!tree.rollback(v2));
+
+        // ids are not reused after a rollback
+        let v4 = tree.update(4, random_scalar());
+        assert_eq!(v4, 4);
+
+        tree.prune(v4 + 10);
+        assert_eq!(tree.versions(), vec![v4]);
+        assert!(!tree.rollback(v1));
+        assert_eq!(tree.prove_at(0, 1), None);
+    }
+}
@@ -0,0 +1,267 @@
+use ark_ff::PrimeField;
+
+use std::marker::PhantomData;
+
+use super::merkle_tree_fp::{domain_tag, node_tag, MerkleTreeHash, SpongeHash};
+use crate::poseidon2::poseidon2::Poseidon2;
+
+const ROW_TAG: u128 = 4;
+
+// A matrix is stored as a list of rows, all of the same width.
+pub type Matrix<F> = Vec<Vec<F>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dimensions {
+    pub height: usize,
+    pub width: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct MmcsProverData<F: PrimeField> {
+    matrices: Vec<Matrix<F>>,
+    // levels[0] are the leaf digests, the last level is the root
+    levels: Vec<Vec<F>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmcsOpening<F: PrimeField> {
+    // one row per committed matrix, in commit order
+    pub rows: Vec<Vec<F>>,
+    pub siblings: Vec<F>,
+}
+
+impl<F: PrimeField> MmcsProverData<F> {
+    pub fn root(&self) -> F {
+        self.levels.last().unwrap()[0]
+    }
+
+    pub fn matrices(&self) -> &[Matrix<F>] {
+        &self.matrices
+    }
+
+    pub fn heights(&self) -> Vec<usize> {
+        self.matrices.iter().map(|m| m.len()).collect()
+    }
+
+    pub fn dimensions(&self) -> Vec<Dimensions> {
+        self.matrices
+            .iter()
+            .map(|m| Dimensions {
+                height: m.len(),
+                width: m[0].len(),
+            })
+            .collect()
+    }
+}
+
+// Mixed matrix commitment: every leaf is the sponge hash of the concatenated
+// rows of all matrices of maximal height. A matrix of smaller height 2^k is
+// hashed row-wise the same way and injected into the level holding 2^k nodes
+// by compressing each node with the corresponding row digest. Rows are hashed
+// with the tag 2^b + 4 + 2^8 * len for len elements in the capacity, apart from
+// every node and injection tag, so row digests can not be passed off as nodes.
+#[derive(Clone, Debug)]
+pub struct Mmcs<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F> = Poseidon2<F>> {
+    perm: P,
+    field: PhantomData<F>,
+}
+
+impl<F: PrimeField, P: MerkleTreeHash<F> + SpongeHash<F>> Mmcs<F, P> {
+    pub fn new(perm: P) -> Self {
+        Mmcs {
+            perm,
+            field: PhantomData,
+        }
+    }
+
+    fn hash_rows<'a, I: Iterator<Item = &'a Vec<F>>>(&self, rows: I) -> F {
+        let input: Vec<F> = rows.flat_map(|r| r.iter().cloned()).collect();
+        let tag = domain_tag(ROW_TAG + ((input.len() as u128) << 8));
+        self.perm.hash_tagged(&input, &tag)
+    }
+
+    // nodes are compressed with the tag of their level as in merkle_tree_fp
+    fn compress(&self, level: usize, left: &F, right: &F) -> F {
+        self.perm.compress_tagged(&[left, right], &node_tag(level, None))
+    }
+
+    // Row digests are injected into a node of height `level` with the tag
+    // 2^b + 3 + 2^8 * level, whose low byte is not used by node_tag.
+    fn inject(&self, level: usize, node: &F, digest: &F) -> F {
+        self.perm
+            .compress_tagged(&[node, digest], &(node_tag::<F>(level, None) + F::one()))
+    }
+
+    pub fn commit(&self, matrices: Vec<Matrix<F>>) -> (F, MmcsProverData<F>) {
+        assert!(!matrices.is_empty());
+        for m in matrices.iter() {
+            assert!(m.len().is_power_of_two());
+            assert!(m.iter().all(|row| row.len() == m[0].len()));
+        }
+        let max_height = matrices.iter().map(|m| m.len()).max().unwrap();
+
+        let tallest: Vec<&Matrix<F>> = matrices.iter().filter(|m| m.len() == max_height).collect();
+        let leaves: Vec<F> = (0..max_height)
+            .map(|i| self.hash_rows(tallest.iter().map(|m| &m[i])))
+            .collect();
+
+        let mut levels = vec![leaves];
+        while levels.last().unwrap().len() > 1 {
+            let lv = levels.len() - 1;
+            let nodes = levels.last().unwrap();
+            let height = nodes.len() / 2;
+            let injected: Vec<&Matrix<F>> = matrices.iter().filter(|m| m.len() == height).collect();
+            let new_nodes = (0..height)
+                .map(|i| {
+                    let node = self.compress(lv, &nodes[2 * i], &nodes[2 * i + 1]);
+                    if injected.is_empty() {
+                        node
+                    } else {
+                        let digest = self.hash_rows(injected.iter().map(|m| &m[i]));
+                        self.inject(lv + 1, &node, &digest)
+                    }
+                })
+                .collect();
+            levels.push(new_nodes);
+        }
+
+        let data = MmcsProverData { matrices, levels };
+        (data.root(), data)
+    }
+
+    // `index` addresses a row of the tallest matrices, shorter matrices are
+    // opened at the row covering it.
+    pub fn open(&self, index: usize, data: &MmcsProverData<F>) -> MmcsOpening<F> {
+        let max_height = data.levels[0].len();
+        assert!(index < max_height);
+        let log_max = max_height.trailing_zeros();
+        let rows = data
+            .matrices
+            .iter()
+            .map(|m| m[index >> (log_max - m.len().trailing_zeros())].to_owned())
+            .collect();
+        let siblings = data.levels[..data.levels.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(lv, nodes)| nodes[(index >> lv) ^ 1])
+            .collect();
+        MmcsOpening { rows, siblings }
+    }
+
+    // Rows have to match the width of their matrix, otherwise elements could be
+    // moved between matrices of the same height, whose rows are hashed together.
+    pub fn verify(&self, root: &F, dims: &[Dimensions], index: usize, opening: &MmcsOpening<F>) -> bool {
+        if dims.is_empty()
+            || dims.len() != opening.rows.len()
+            || dims.iter().any(|d| !d.height.is_power_of_two())
+            || dims.iter().zip(opening.rows.iter()).any(|(d, r)| d.width != r.len())
+        {
+            return false;
+        }
+        let heights: Vec<usize> = dims.iter().map(|d| d.height).collect();
+        let max_height = *heights.iter().max().unwrap();
+        if index >= max_height || opening.siblings.len() != max_height.trailing_zeros() as usize {
+            return false;
+        }
+
+        let rows_of_height = |height: usize| {
+            heights
+                .iter()
+                .zip(opening.rows.iter())
+                .filter(move |(h, _)| **h == height)
+                .map(|(_, r)| r)
+        };
+
+        let mut acc = self.hash_rows(rows_of_height(max_height));
+        let mut height = max_height;
+        for (lv, sibling) in opening.siblings.iter().enumerate() {
+            acc = if (index >> lv) & 1 == 0 {
+                self.compress(lv, &acc, sibling)
+            } else {
+                self.compress(lv, sibling, &acc)
+            };
+            height /= 2;
+            if heights.contains(&height) {
+                let digest = self.hash_rows(rows_of_height(height));
+                acc = self.inject(lv + 1, &acc, &digest);
+            }
+        }
+        acc == *root
+    }
+}
+
+#[cfg(test)]
+mod mmcs_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+
+    type Scalar = FpBN256;
+
+    fn random_matrix(height: usize, width: usize) -> Matrix<Scalar> {
+        (0..height)
+            .map(|_| (0..width).map(|_| random_scalar()).collect())
+            .collect()
+    }
+
+    fn mmcs() -> Mmcs<Scalar> {
+        Mmcs::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS))
+    }
+
+    #[test]
+    fn open_and_verify() {
+        let mmcs = mmcs();
+        let matrices = vec![
+            random_matrix(8, 3),
+            random_matrix(2, 5),
+            random_matrix(8, 13),
+            random_matrix(4, 1),
+            random_matrix(2, 2),
+        ];
+        let (root, data) = mmcs.commit(matrices);
+        let dims = data.dimensions();
+
+        for index in 0..8 {
+            let opening = mmcs.open(index, &data);
+            assert_eq!(opening.rows[1], data.matrices()[1][index >> 2]);
+            assert_eq!(opening.rows[3], data.matrices()[3][index >> 1]);
+            assert!(mmcs.verify(&root, &dims, index, &opening));
+            assert!(!mmcs.verify(&root, &dims, index ^ 1, &opening));
+
+            let mut tampered = opening.clone();
+            tampered.rows[4][1] = random_scalar();
+            assert!(!mmcs.verify(&root, &dims, index, &tampered));
+
+            // matrices 0 and 2 share a height, their rows are hashed together
+            let mut shifted = opening.clone();
+            let el = shifted.rows[2].remove(0);
+            shifted.rows[0].push(el);
+            assert!(!mmcs.verify(&root, &dims, index, &shifted));
+        }
+    }
+
+    #[test]
+    fn row_digests_are_not_nodes() {
+        let mmcs = mmcs();
+        let matrix = random_matrix(2, 3);
+        // a single row holding the row digests of a 2 x 3 matrix
+        let digests: Matrix<Scalar> = vec![matrix.iter().map(|r| mmcs.hash_rows(std::iter::once(r))).collect()];
+        let (root, _) = mmcs.commit(vec![matrix]);
+        let (forged_root, forged) = mmcs.commit(vec![digests]);
+        assert_ne!(forged_root, root);
+        let opening = mmcs.open(0, &forged);
+        assert!(!mmcs.verify(&root, &forged.dimensions(), 0, &opening));
+    }
+
+    #[test]
+    fn single_matrix_is_a_merkle_tree_over_row_hashes() {
+        let mmcs = mmcs();
+        let matrix = random_matrix(16, 4);
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let tag = domain_tag::<Scalar>(ROW_TAG + (4 << 8));
+        let leaves: Vec<Scalar> = matrix.iter().map(|r| perm.hash_tagged(r, &tag)).collect();
+        let mut mt = crate::merkle_tree::merkle_tree_fp::MerkleTree::new(perm);
+        let (root, _) = mmcs.commit(vec![matrix]);
+        assert_eq!(root, mt.accumulate(&leaves));
+    }
+}
@@ -0,0 +1,337 @@
+use super::merkle_tree::Compression;
+
+// Level of the final compression binding the number of leaves into the root,
+// above the height of every peak and bagging node.
+const SIZE_LEVEL: usize = usize::BITS as usize;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmrProof<N> {
+    pub size: usize,
+    pub index: usize,
+    pub path: Vec<N>,
+    pub peaks: Vec<N>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmrPrefixProof<N> {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub old_peaks: Vec<N>,
+    // one path per old peak, leading up to the new peak covering it
+    pub paths: Vec<Vec<N>>,
+    pub new_peaks: Vec<N>,
+}
+
+// Nodes are stored per height: levels[h][i] is the root of the perfect subtree
+// over the leaves [i * 2^h, (i + 1) * 2^h). Only complete subtrees exist, so
+// appending never changes a previously computed node.
+#[derive(Clone, Debug)]
+pub struct MerkleMountainRange<H: Compression> {
+    compression: H,
+    levels: Vec<Vec<H::Node>>,
+}
+
+impl<H: Compression> MerkleMountainRange<H> {
+    pub fn new(compression: H) -> Self {
+        MerkleMountainRange {
+            compression,
+            levels: vec![Vec::new()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    pub fn append(&mut self, leaf: H::Node) -> usize {
+        let index = self.len();
+        self.levels[0].push(leaf);
+
+        let mut h = 0;
+        while self.levels[h].len() & 1 == 0 {
+            let len = self.levels[h].len();
+            let node = {
+                let inp = [&self.levels[h][len - 2], &self.levels[h][len - 1]];
+                self.compression.compress(h, len / 2 - 1, &inp)
+            };
+            if self.levels.len() == h + 1 {
+                self.levels.push(Vec::new());
+            }
+            self.levels[h + 1].push(node);
+            h += 1;
+        }
+        index
+    }
+
+    pub fn extend(&mut self, set: &[H::Node]) {
+        for s in set {
+            self.append(s.to_owned());
+        }
+    }
+
+    // (height, index within level) of the peaks of an MMR with `size` leaves, left to right
+    fn peak_positions(size: usize) -> Vec<(usize, usize)> {
+        let mut positions = Vec::with_capacity(size.count_ones() as usize);
+        for h in (0..usize::BITS as usize).rev() {
+            if (size >> h) & 1 == 1 {
+                positions.push((h, (size >> h) - 1));
+            }
+        }
+        positions
+    }
+
+    // index into the peak list and height of the peak above node (height, index)
+    fn covering_peak(size: usize, height: usize, index: usize) -> Option<(usize, usize)> {
+        Self::peak_positions(size)
+            .into_iter()
+            .enumerate()
+            .find(|(_, (h, i))| *h >= height && index >> (h - height) == *i)
+            .map(|(p, (h, _))| (p, h))
+    }
+
+    fn peaks_at(&self, size: usize) -> Vec<H::Node> {
+        Self::peak_positions(size)
+            .into_iter()
+            .map(|(h, i)| self.levels[h][i].to_owned())
+            .collect()
+    }
+
+    pub fn peaks(&self) -> Vec<H::Node> {
+        self.peaks_at(self.len())
+    }
+
+    // Bag the peaks from right to left. Each bagging node is compressed at the
+    // level and parent position of the peak to its left. The result is finally
+    // compressed with the number of leaves, so a proof can not claim a size
+    // whose peaks are inner nodes of the committed MMR.
+    fn bag(&mut self, size: usize, peaks: &[H::Node]) -> Option<H::Node> {
+        let positions = Self::peak_positions(size);
+        debug_assert_eq!(positions.len(), peaks.len());
+        let (last, rest) = peaks.split_last()?;
+        let mut acc = last.to_owned();
+        for (p, (h, i)) in rest.iter().zip(positions[..rest.len()].iter()).rev() {
+            acc = self.compression.compress(*h, i >> 1, &[p, &acc]);
+        }
+        let size = self.compression.length(size);
+        Some(self.compression.compress(SIZE_LEVEL, 0, &[&acc, &size]))
+    }
+
+    pub fn root_at(&mut self, size: usize) -> Option<H::Node> {
+        assert!(size <= self.len());
+        let peaks = self.peaks_at(size);
+        self.bag(size, &peaks)
+    }
+
+    pub fn root(&mut self) -> Option<H::Node> {
+        self.root_at(self.len())
+    }
+
+    fn path(&self, height: usize, index: usize, peak_height: usize) -> Vec<H::Node> {
+        (height..peak_height)
+            .map(|h| self.levels[h][(index >> (h - height)) ^ 1].to_owned())
+            .collect()
+    }
+
+    fn climb(&mut self, node: &H::Node, height: usize, index: usize, path: &[H::Node]) -> H::Node {
+        let mut acc = node.to_owned();
+        for (k, sibling) in path.iter().enumerate() {
+            let pos = index >> (k + 1);
+            acc = if (index >> k) & 1 == 0 {
+                self.compression.compress(height + k, pos, &[&acc, sibling])
+            } else {
+                self.compression.compress(height + k, pos, &[sibling, &acc])
+            };
+        }
+        acc
+    }
+
+    pub fn prove(&self, index: usize) -> MmrProof<H::Node> {
+        let size = self.len();
+        assert!(index < size);
+        let (_, peak_height) = Self::covering_peak(size, 0, index).unwrap();
+        MmrProof {
+            size,
+            index,
+            path: self.path(0, index, peak_height),
+            peaks: self.peaks(),
+        }
+    }
+
+    pub fn verify(&mut self, root: &H::Node, leaf: &H::Node, proof: &MmrProof<H::Node>) -> bool {
+        if proof.index >= proof.size || proof.peaks.len() != proof.size.count_ones() as usize {
+            return false;
+        }
+        let (p, peak_height) = match Self::covering_peak(proof.size, 0, proof.index) {
+            Some(x) => x,
+            None => return false,
+        };
+        if proof.path.len() != peak_height {
+            return false;
+        }
+        let peak = self.climb(leaf, 0, proof.index, &proof.path);
+        if peak != proof.peaks[p] {
+            return false;
+        }
+        self.bag(proof.size, &proof.peaks).as_ref() == Some(root)
+    }
+
+    pub fn prove_prefix(&self, old_size: usize) -> MmrPrefixProof<H::Node> {
+        let new_size = self.len();
+        assert!(old_size >= 1 && old_size <= new_size);
+        let paths = Self::peak_positions(old_size)
+            .into_iter()
+            .map(|(h, i)| {
+                let (_, peak_height) = Self::covering_peak(new_size, h, i).unwrap();
+                self.path(h, i, peak_height)
+            })
+            .collect();
+        MmrPrefixProof {
+            old_size,
+            new_size,
+            old_peaks: self.peaks_at(old_size),
+            paths,
+            new_peaks: self.peaks(),
+        }
+    }
+
+    pub fn verify_prefix(
+        &mut self,
+        old_root: &H::Node,
+        new_root: &H::Node,
+        proof: &MmrPrefixProof<H::Node>,
+    ) -> bool {
+        let old_positions = Self::peak_positions(proof.old_size);
+        if proof.old_size == 0
+            || proof.old_size > proof.new_size
+            || proof.old_peaks.len() != old_positions.len()
+            || proof.paths.len() != old_positions.len()
+            || proof.new_peaks.len() != proof.new_size.count_ones() as usize
+        {
+            return false;
+        }
+        if self.bag(proof.old_size, &proof.old_peaks).as_ref() != Some(old_root) {
+            return false;
+        }
+        for (((h, i), old_peak), path) in old_positions
+            .into_iter()
+            .zip(proof.old_peaks.iter())
+            .zip(proof.paths.iter())
+        {
+            let (p, peak_height) = match Self::covering_peak(proof.new_size, h, i) {
+                Some(x) => x,
+                None => return false,
+            };
+            if path.len() != peak_height - h {
+                return false;
+            }
+            if self.climb(old_peak, h, i, path) != proof.new_peaks[p] {
+                return false;
+            }
+        }
+        self.bag(proof.new_size, &proof.new_peaks).as_ref() == Some(new_root)
+    }
+}
+
+#[cfg(test)]
+mod mmr_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::merkle_tree::{merkle_tree_f2::DigestCompression, merkle_tree_fp::FpCompression};
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+    use sha2::{Digest, Sha256};
+
+    type Scalar = FpBN256;
+
+    fn fp_mmr() -> MerkleMountainRange<FpCompression<Scalar, Poseidon2<Scalar>>> {
+        MerkleMountainRange::new(FpCompression::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS)))
+    }
+
+    #[test]
+    fn peaks_match_binary_decomposition() {
+        let mut mmr = fp_mmr();
+        for i in 0..13 {
+            mmr.append(Scalar::from(i as u64));
+            assert_eq!(mmr.peaks().len(), mmr.len().count_ones() as usize);
+        }
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let leaves: Vec<Scalar> = (0..13).map(|i| Scalar::from(i as u64)).collect();
+        let mut tree8 = crate::merkle_tree::merkle_tree_fp::MerkleTree::new(perm.clone());
+        let mut tree4 = crate::merkle_tree::merkle_tree_fp::MerkleTree::new(perm);
+        let peaks = mmr.peaks();
+        assert_eq!(peaks[0], tree8.accumulate(&leaves[0..8]));
+        assert_eq!(peaks[1], tree4.accumulate(&leaves[8..12]));
+        assert_eq!(peaks[2], leaves[12]);
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        let mut mmr = fp_mmr();
+        let leaves: Vec<Scalar> = (0..11).map(|_| random_scalar()).collect();
+        mmr.extend(&leaves);
+        let root = mmr.root().unwrap();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let proof = mmr.prove(i);
+            assert!(mmr.verify(&root, leaf, &proof));
+            assert!(!mmr.verify(&root, &random_scalar(), &proof));
+        }
+    }
+
+    #[test]
+    fn prefix_proofs() {
+        let mut mmr = fp_mmr();
+        let leaves: Vec<Scalar> = (0..19).map(|_| random_scalar()).collect();
+        mmr.extend(&leaves);
+        let new_root = mmr.root().unwrap();
+        for old_size in 1..=leaves.len() {
+            let old_root = mmr.root_at(old_size).unwrap();
+            let proof = mmr.prove_prefix(old_size);
+            assert!(mmr.verify_prefix(&old_root, &new_root, &proof));
+            assert!(!mmr.verify_prefix(&random_scalar(), &new_root, &proof));
+        }
+    }
+
+    #[test]
+    fn size_bound_into_root() {
+        // the peaks of 3 leaves bag like the single peak of 4 leaves
+        let mut mmr = fp_mmr();
+        let leaves: Vec<Scalar> = (0..4).map(|_| random_scalar()).collect();
+        mmr.extend(&leaves);
+        let root = mmr.root().unwrap();
+        let peaks = vec![mmr.levels[1][0], mmr.levels[1][1]];
+        let forged = MmrProof {
+            size: 3,
+            index: 2,
+            path: vec![],
+            peaks: peaks.clone(),
+        };
+        assert!(!mmr.verify(&root, &peaks[1], &forged));
+        assert_ne!(mmr.root_at(3), mmr.root_at(4));
+
+        let forged = MmrPrefixProof {
+            old_size: 3,
+            new_size: 3,
+            old_peaks: peaks.clone(),
+            paths: vec![vec![], vec![]],
+            new_peaks: peaks,
+        };
+        assert!(!mmr.verify_prefix(&root, &root, &forged));
+    }
+
+    #[test]
+    fn digest_mmr() {
+        let mut mmr = MerkleMountainRange::new(DigestCompression::<Sha256>::new());
+        let leaves: Vec<_> = (0..6u8).map(|i| Sha256::digest([i])).collect();
+        mmr.extend(&leaves);
+        let root = mmr.root().unwrap();
+        let proof = mmr.prove(5);
+        assert!(mmr.verify(&root, &leaves[5], &proof));
+        assert!(!mmr.verify(&root, &leaves[4], &proof));
+        let prefix = mmr.prove_prefix(3);
+        let old_root = mmr.root_at(3).unwrap();
+        assert!(mmr.verify_prefix(&old_root, &root, &prefix));
+    }
+}
@@ -1,4 +1,19 @@
+pub mod indexed_merkle_tree;
+#[allow(clippy::module_inception)]
+pub mod merkle_tree;
 pub mod merkle_tree_f2;
 pub mod merkle_tree_fp;
+pub mod merkle_tree_hiding;
+pub mod merkle_tree_hybrid;
+pub mod merkle_tree_multi;
 pub mod merkle_tree_orchard;
+pub mod merkle_tree_rfc6962;
 pub mod merkle_tree_sapling;
+pub mod merkle_tree_sum;
+pub mod merkle_tree_sync;
+pub mod merkle_tree_versioned;
+pub mod mmcs;
+pub mod mmr;
+pub mod padding;
+pub mod storage;
+pub mod stored_merkle_tree;
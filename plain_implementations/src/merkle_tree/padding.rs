@@ -0,0 +1,229 @@
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Padding<N> {
+    // Duplicate the last leaf up to the next power of two. Panics on an empty set.
+    Legacy,
+    // Pad with the zero leaf up to the next power of two.
+    Zero,
+    // Complete every odd level with the root of an empty subtree of matching
+    // height, where the empty subtree is built from the given leaf.
+    EmptySubtree(N),
+    // Carry an unpaired node up to the next level unchanged (unbalanced tree).
+    #[default]
+    Promote,
+    // Zero padding, followed by compressing the root with the number of leaves.
+    LengthBound,
+}
+
+pub(crate) fn round_up_pow_n(input: usize, n: usize) -> usize {
+    debug_assert!(n >= 1);
+    let mut res = 1;
+    // try powers, starting from n
+    loop {
+        res *= n;
+        if res >= input {
+            break;
+        }
+    }
+    res
+}
+
+// Leaf level after padding.
+pub(crate) fn prepare<N: Clone>(set: &[N], padding: &Padding<N>, zero: &N) -> Vec<N> {
+    let set_size = set.len();
+    let mut nodes = set.to_owned();
+    match padding {
+        Padding::Legacy | Padding::Zero | Padding::LengthBound => {
+            let bound = round_up_pow_n(set_size, 2).max(2);
+            // pad
+            for _ in set_size..bound {
+                let p = match padding {
+                    Padding::Legacy => nodes[set_size - 1].to_owned(),
+                    _ => zero.to_owned(),
+                };
+                nodes.push(p);
+            }
+        }
+        Padding::EmptySubtree(empty) => {
+            if set_size < 2 {
+                nodes.resize(2, empty.to_owned());
+            }
+        }
+        Padding::Promote => {
+            if set_size == 0 {
+                nodes.push(zero.to_owned());
+            }
+        }
+    }
+    nodes
+}
+
+// Length of the leaf level after padding a set of `set_size` leaves.
+pub(crate) fn prepared_len<N>(set_size: usize, padding: &Padding<N>) -> usize {
+    match padding {
+        Padding::Legacy | Padding::Zero | Padding::LengthBound => round_up_pow_n(set_size, 2).max(2),
+        Padding::EmptySubtree(_) => set_size.max(2),
+        Padding::Promote => set_size.max(1),
+    }
+}
+
+// How a level with an odd number of nodes is completed.
+#[derive(Clone, Debug)]
+pub(crate) enum OddRule<N> {
+    // never happens, e.g. for power-of-two padded levels
+    Never,
+    // pair the last node with this empty subtree root of the current level
+    Empty(N),
+    Promote,
+}
+
+impl<N: Clone> OddRule<N> {
+    pub(crate) fn new(padding: &Padding<N>) -> Self {
+        match padding {
+            Padding::EmptySubtree(empty) => OddRule::Empty(empty.to_owned()),
+            Padding::Promote => OddRule::Promote,
+            _ => OddRule::Never,
+        }
+    }
+}
+
+// Reduces `nodes`, which sit at height `level` starting at index `offset`
+// within that level, either for a fixed number of rounds or down to a single
+// node. Empty subtree roots are computed at position 0.
+pub(crate) fn reduce<N: Clone, C: FnMut(usize, usize, &[&N; 2]) -> N>(
+    mut nodes: Vec<N>,
+    mut level: usize,
+    mut offset: usize,
+    rounds: Option<usize>,
+    odd: &mut OddRule<N>,
+    compress: &mut C,
+) -> Vec<N> {
+    let mut round = 0;
+    while rounds.map_or(nodes.len() > 1, |r| round < r) {
+        let mut carry = None;
+        if nodes.len() % 2 == 1 {
+            match odd {
+                OddRule::Never => panic!("unpadded level"),
+                OddRule::Empty(empty) => nodes.push(empty.to_owned()),
+                OddRule::Promote => carry = nodes.pop(),
+            }
+        }
+        let mut new_nodes: Vec<N> = Vec::with_capacity(nodes.len() / 2 + 1);
+        for i in (0..nodes.len()).step_by(2) {
+            let inp = [&nodes[i], &nodes[i + 1]];
+            let dig = compress(level, (offset + i) / 2, &inp);
+            new_nodes.push(dig);
+        }
+        new_nodes.extend(carry);
+        if let OddRule::Empty(empty) = odd {
+            *empty = compress(level, 0, &[empty, empty]);
+        }
+        level += 1;
+        offset /= 2;
+        round += 1;
+        nodes = new_nodes;
+    }
+    nodes
+}
+
+// Applied to the root of a prepared leaf level of size `prepared_len`.
+pub(crate) fn finalize<N: Clone, C: FnMut(usize, usize, &[&N; 2]) -> N>(
+    root: N,
+    prepared_len: usize,
+    padding: &Padding<N>,
+    length: N,
+    compress: &mut C,
+) -> N {
+    match padding {
+        Padding::LengthBound => {
+            let depth = prepared_len.trailing_zeros() as usize;
+            compress(depth, 0, &[&root, &length])
+        }
+        _ => root,
+    }
+}
+
+// Computes the root of `set` under `padding`. `compress` receives the height of
+// the two input nodes and the index of the resulting node within its level.
+pub(crate) fn accumulate<N: Clone, C: FnMut(usize, usize, &[&N; 2]) -> N>(
+    set: &[N],
+    padding: &Padding<N>,
+    zero: &N,
+    length: N,
+    mut compress: C,
+) -> N {
+    let nodes = prepare(set, padding, zero);
+    let prepared_len = nodes.len();
+    let mut odd = OddRule::new(padding);
+    let root = reduce(nodes, 0, 0, None, &mut odd, &mut compress).remove(0);
+    finalize(root, prepared_len, padding, length, &mut compress)
+}
+
+#[cfg(test)]
+mod padding_tests {
+    use super::*;
+
+    // a non-commutative toy compression which records its inputs
+    fn compress(lv: usize, _pos: usize, input: &[&String; 2]) -> String {
+        format!("({}{},{})", lv, input[0], input[1])
+    }
+
+    fn set(n: usize) -> Vec<String> {
+        (0..n).map(|i| ((b'a' + i as u8) as char).to_string()).collect()
+    }
+
+    fn root(n: usize, padding: &Padding<String>) -> String {
+        accumulate(&set(n), padding, &"0".to_string(), n.to_string(), compress)
+    }
+
+    #[test]
+    fn legacy_duplicates_last_leaf() {
+        assert_eq!(root(3, &Padding::Legacy), "(1(0a,b),(0c,c))");
+        assert_eq!(root(1, &Padding::Legacy), "(0a,a)");
+        let mut four = set(3);
+        four.push("c".to_string());
+        let four = accumulate(&four, &Padding::Legacy, &"0".to_string(), String::new(), compress);
+        assert_eq!(root(3, &Padding::Legacy), four);
+    }
+
+    #[test]
+    #[should_panic]
+    fn legacy_panics_on_empty_set() {
+        root(0, &Padding::Legacy);
+    }
+
+    #[test]
+    fn strategies() {
+        assert_eq!(root(3, &Padding::Zero), "(1(0a,b),(0c,0))");
+        assert_eq!(root(0, &Padding::Zero), "(00,0)");
+        assert_eq!(
+            root(5, &Padding::EmptySubtree("x".to_string())),
+            "(2(1(0a,b),(0c,d)),(1(0e,x),(0x,x)))"
+        );
+        assert_eq!(root(3, &Padding::Promote), "(1(0a,b),c)");
+        assert_eq!(root(1, &Padding::Promote), "a");
+        assert_eq!(root(0, &Padding::Promote), "0");
+        assert_eq!(root(3, &Padding::LengthBound), "(2(1(0a,b),(0c,0)),3)");
+    }
+
+    #[test]
+    fn positions() {
+        let mut seen = Vec::new();
+        accumulate(&set(5), &Padding::Promote, &"0".to_string(), String::new(), |lv, pos, inp| {
+            seen.push((lv, pos));
+            compress(lv, pos, inp)
+        });
+        assert_eq!(seen, vec![(0, 0), (0, 1), (1, 0), (2, 0)]);
+    }
+
+    #[test]
+    fn power_of_two_sets_are_unpadded() {
+        for padding in [
+            Padding::Legacy,
+            Padding::Zero,
+            Padding::EmptySubtree("e".to_string()),
+            Padding::Promote,
+        ] {
+            assert_eq!(root(8, &padding), root(8, &Padding::Legacy));
+        }
+    }
+}
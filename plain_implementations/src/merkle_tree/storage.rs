@@ -0,0 +1,390 @@
+use ark_ff::PrimeField;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::fields::utils::{canonical_byte_len, from_canonical_bytes, to_canonical_bytes};
+
+// Backing store of the nodes of a StoredMerkleTree. Level l holds 2^(depth - l)
+// nodes, level 0 are the leaves and level depth is the root.
+pub trait NodeStorage<N> {
+    fn depth(&self) -> usize;
+    fn get(&self, level: usize, index: usize) -> io::Result<N>;
+    fn set(&mut self, level: usize, index: usize, node: N) -> io::Result<()>;
+    // makes all writes since the last flush persistent
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemoryStorage<N> {
+    levels: Vec<Vec<N>>,
+}
+
+impl<N: Clone> MemoryStorage<N> {
+    // The inner levels are filled with copies of the first leaf until the tree
+    // computes them.
+    pub fn from_leaves(leaves: Vec<N>) -> Self {
+        assert!(leaves.len().is_power_of_two());
+        let depth = leaves.len().trailing_zeros() as usize;
+        let mut levels = Vec::with_capacity(depth + 1);
+        for lv in 1..=depth {
+            levels.push(vec![leaves[0].to_owned(); 1 << (depth - lv)]);
+        }
+        levels.insert(0, leaves);
+        MemoryStorage { levels }
+    }
+
+    pub fn level(&self, level: usize) -> &[N] {
+        &self.levels[level]
+    }
+}
+
+impl<N: Clone> NodeStorage<N> for MemoryStorage<N> {
+    fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    fn get(&self, level: usize, index: usize) -> io::Result<N> {
+        Ok(self.levels[level][index].to_owned())
+    }
+
+    fn set(&mut self, level: usize, index: usize, node: N) -> io::Result<()> {
+        self.levels[level][index] = node;
+        Ok(())
+    }
+}
+
+const MAGIC: &[u8; 8] = b"ZKHASHMT";
+// magic, depth and node length as little-endian u64
+const HEADER_LEN: u64 = 24;
+// the offset of the last level overflows a u64 for large depths
+const MAX_DEPTH: usize = 48;
+const DEFAULT_MAX_DIRTY: usize = 1 << 16;
+// nodes per page and number of cached pages
+const PAGE_NODES: usize = 256;
+const DEFAULT_MAX_PAGES: usize = 1 << 10;
+
+// Pages of consecutive nodes of one level, evicted first in, first out.
+#[derive(Debug)]
+struct PageCache<F> {
+    pages: HashMap<(usize, usize), Vec<F>>,
+    order: VecDeque<(usize, usize)>,
+    max_pages: usize,
+}
+
+impl<F> PageCache<F> {
+    fn new(max_pages: usize) -> Self {
+        PageCache {
+            pages: HashMap::new(),
+            order: VecDeque::new(),
+            max_pages,
+        }
+    }
+
+    fn insert(&mut self, key: (usize, usize), page: Vec<F>) {
+        while self.pages.len() >= self.max_pages.max(1) {
+            let oldest = self.order.pop_front().unwrap();
+            self.pages.remove(&oldest);
+        }
+        self.order.push_back(key);
+        self.pages.insert(key, page);
+    }
+}
+
+// Stores all levels one after another in a single file, every node in the
+// canonical little-endian encoding of its field element. Reads load whole
+// pages of PAGE_NODES nodes, which are kept in a bounded cache. Writes are
+// buffered and only the modified nodes are written back, either on flush or
+// once more than max_dirty nodes are pending. A freshly created file is all
+// zero bytes, i.e., every node is zero.
+#[derive(Debug)]
+pub struct FileStorage<F: PrimeField> {
+    file: File,
+    depth: usize,
+    node_len: usize,
+    dirty: BTreeMap<(usize, usize), F>,
+    max_dirty: usize,
+    cache: RefCell<PageCache<F>>,
+}
+
+impl<F: PrimeField> FileStorage<F> {
+    pub fn create<P: AsRef<Path>>(path: P, depth: usize) -> io::Result<Self> {
+        if depth == 0 || depth >= MAX_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported depth"));
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let node_len = canonical_byte_len::<F>();
+        file.write_all(MAGIC)?;
+        file.write_all(&(depth as u64).to_le_bytes())?;
+        file.write_all(&(node_len as u64).to_le_bytes())?;
+        let storage = FileStorage {
+            file,
+            depth,
+            node_len,
+            dirty: BTreeMap::new(),
+            max_dirty: DEFAULT_MAX_DIRTY,
+            cache: RefCell::new(PageCache::new(DEFAULT_MAX_PAGES)),
+        };
+        let len = storage.offset(depth + 1, 0);
+        storage.file.set_len(len)?;
+        Ok(storage)
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if &header[..8] != MAGIC {
+            return Err(invalid("not a Merkle tree file"));
+        }
+        let depth = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+        let node_len = u64::from_le_bytes(header[16..24].try_into().unwrap()) as usize;
+        if depth == 0 || depth >= MAX_DEPTH {
+            return Err(invalid("unsupported depth"));
+        }
+        if node_len != canonical_byte_len::<F>() {
+            return Err(invalid("node length does not match the field"));
+        }
+        let storage = FileStorage {
+            file,
+            depth,
+            node_len,
+            dirty: BTreeMap::new(),
+            max_dirty: DEFAULT_MAX_DIRTY,
+            cache: RefCell::new(PageCache::new(DEFAULT_MAX_PAGES)),
+        };
+        if storage.file.metadata()?.len() != storage.offset(depth + 1, 0) {
+            return Err(invalid("unexpected file length"));
+        }
+        Ok(storage)
+    }
+
+    pub fn set_max_dirty(&mut self, max_dirty: usize) {
+        self.max_dirty = max_dirty;
+    }
+
+    pub fn pending(&self) -> usize {
+        self.dirty.len()
+    }
+
+    pub fn set_max_pages(&mut self, max_pages: usize) {
+        self.cache.get_mut().max_pages = max_pages;
+    }
+
+    pub fn cached_pages(&self) -> usize {
+        self.cache.borrow().pages.len()
+    }
+
+    // Levels are stored from the leaves upwards, the nodes of level l start
+    // after 2^(depth + 1) - 2^(depth + 1 - l) nodes.
+    fn offset(&self, level: usize, index: usize) -> u64 {
+        let before = (1u64 << (self.depth + 1)) - (1u64 << (self.depth + 1 - level));
+        HEADER_LEN + (before + index as u64) * self.node_len as u64
+    }
+
+    fn check_index(&self, level: usize, index: usize) {
+        assert!(level <= self.depth && index >> (self.depth - level) == 0);
+    }
+
+    fn read_page(&self, level: usize, page: usize) -> io::Result<Vec<F>> {
+        let len = PAGE_NODES.min(1 << (self.depth - level));
+        let mut bytes = vec![0u8; len * self.node_len];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset(level, page * PAGE_NODES)))?;
+        file.read_exact(&mut bytes)?;
+        bytes
+            .chunks(self.node_len)
+            .map(|chunk| {
+                from_canonical_bytes(chunk)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "non-canonical node encoding"))
+            })
+            .collect()
+    }
+
+    // Writes the pending nodes into the file and keeps the cached pages
+    // up to date.
+    fn write_back(&mut self) -> io::Result<()> {
+        let dirty = std::mem::take(&mut self.dirty);
+        for ((level, index), node) in dirty {
+            self.file.seek(SeekFrom::Start(self.offset(level, index)))?;
+            self.file.write_all(&to_canonical_bytes(&node))?;
+            if let Some(page) = self.cache.get_mut().pages.get_mut(&(level, index / PAGE_NODES)) {
+                page[index % PAGE_NODES] = node;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: PrimeField> NodeStorage<F> for FileStorage<F> {
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn get(&self, level: usize, index: usize) -> io::Result<F> {
+        self.check_index(level, index);
+        if let Some(node) = self.dirty.get(&(level, index)) {
+            return Ok(*node);
+        }
+        let key = (level, index / PAGE_NODES);
+        if let Some(page) = self.cache.borrow().pages.get(&key) {
+            return Ok(page[index % PAGE_NODES]);
+        }
+        let page = self.read_page(level, key.1)?;
+        let node = page[index % PAGE_NODES];
+        self.cache.borrow_mut().insert(key, page);
+        Ok(node)
+    }
+
+    fn set(&mut self, level: usize, index: usize, node: F) -> io::Result<()> {
+        self.check_index(level, index);
+        self.dirty.insert((level, index), node);
+        if self.dirty.len() > self.max_dirty {
+            self.write_back()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_back()?;
+        self.file.sync_data()
+    }
+}
+
+impl<F: PrimeField> Drop for FileStorage<F> {
+    fn drop(&mut self) {
+        let _ = self.write_back();
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::merkle_tree::merkle_tree_fp::FpCompression;
+    use crate::merkle_tree::stored_merkle_tree::StoredMerkleTree;
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+    use std::path::PathBuf;
+
+    type Scalar = FpBN256;
+
+    fn compression() -> FpCompression<Scalar, Poseidon2<Scalar>> {
+        FpCompression::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("zkhash_{}_{}.mt", name, std::process::id()))
+    }
+
+    #[test]
+    fn canonical_encoding() {
+        let x: Scalar = random_scalar();
+        let bytes = to_canonical_bytes(&x);
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(from_canonical_bytes::<Scalar>(&bytes), Some(x));
+        assert_eq!(from_canonical_bytes::<Scalar>(&[0xff; 32]), None);
+        assert_eq!(from_canonical_bytes::<Scalar>(&bytes[..31]), None);
+    }
+
+    #[test]
+    fn file_tree_persists_and_reopens() {
+        let path = temp_path("reopen");
+        let leaves: Vec<Scalar> = (0..64).map(|_| random_scalar()).collect();
+        let mut memory = StoredMerkleTree::new(compression(), leaves.clone());
+
+        let mut storage = FileStorage::<Scalar>::create(&path, 6).unwrap();
+        storage.set_max_dirty(20);
+        for (i, leaf) in leaves.iter().enumerate() {
+            storage.set(0, i, *leaf).unwrap();
+        }
+        let mut tree = StoredMerkleTree::build(compression(), storage).unwrap();
+        assert_eq!(tree.storage().pending(), 0);
+        assert_eq!(tree.root().unwrap(), memory.root().unwrap());
+
+        let updates = vec![(3, random_scalar()), (40, random_scalar())];
+        tree.batch_update(&updates).unwrap();
+        memory.batch_update(&updates).unwrap();
+        // only the leaves and their ancestors are written back
+        assert_eq!(tree.storage().pending(), 2 * 6 + 1);
+        tree.flush().unwrap();
+        let root = tree.root().unwrap();
+        assert_eq!(root, memory.root().unwrap());
+        drop(tree);
+
+        let mut tree = StoredMerkleTree::open(compression(), FileStorage::<Scalar>::open(&path).unwrap());
+        assert_eq!(tree.depth(), 6);
+        assert_eq!(tree.root().unwrap(), root);
+        for i in [0, 3, 40, 63] {
+            let proof = tree.prove(i).unwrap();
+            assert_eq!(proof, memory.prove(i).unwrap());
+            let leaf = tree.leaf(i).unwrap();
+            assert!(tree.verify(&root, i, &leaf, &proof));
+        }
+
+        // unflushed writes are persisted on drop
+        tree.update(7, random_scalar()).unwrap();
+        let root = tree.root().unwrap();
+        drop(tree);
+        let tree = StoredMerkleTree::open(compression(), FileStorage::<Scalar>::open(&path).unwrap());
+        assert_eq!(tree.root().unwrap(), root);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_rejects_other_fields() {
+        let path = temp_path("field");
+        FileStorage::<Scalar>::create(&path, 2).unwrap();
+        assert!(FileStorage::<crate::fields::goldilocks::FpGoldiLocks>::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn create_rejects_unsupported_depths() {
+        let path = temp_path("depth");
+        assert!(FileStorage::<Scalar>::create(&path, 0).is_err());
+        assert!(FileStorage::<Scalar>::create(&path, MAX_DEPTH).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn paged_reads() {
+        let path = temp_path("pages");
+        let leaves: Vec<Scalar> = (0..1024).map(|_| random_scalar()).collect();
+        let mut storage = FileStorage::<Scalar>::create(&path, 10).unwrap();
+        storage.set_max_pages(2);
+        for (i, leaf) in leaves.iter().enumerate() {
+            storage.set(0, i, *leaf).unwrap();
+        }
+        let tree = StoredMerkleTree::build(compression(), storage).unwrap();
+        let memory = StoredMerkleTree::new(compression(), leaves.clone());
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert_eq!(tree.leaf(i).unwrap(), *leaf);
+        }
+        assert_eq!(tree.storage().cached_pages(), 2);
+        assert_eq!(tree.prove(700).unwrap(), memory.prove(700).unwrap());
+        drop(tree);
+
+        // a corrupted node is reported instead of panicking
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(HEADER_LEN)).unwrap();
+        file.write_all(&[0xff; 32]).unwrap();
+        drop(file);
+        let storage = FileStorage::<Scalar>::open(&path).unwrap();
+        assert!(storage.get(0, 0).is_err());
+        assert!(storage.get(1, 0).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
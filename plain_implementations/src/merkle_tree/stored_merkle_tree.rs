@@ -0,0 +1,273 @@
+use super::merkle_tree::{climb, Compression, MerkleProof};
+use std::io;
+
+use super::storage::{MemoryStorage, NodeStorage};
+
+// One step of a state transition. The path is valid both for the old leaf in
+// the tree before the step and for the new leaf after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpdateWitness<N> {
+    pub index: usize,
+    pub old_leaf: N,
+    pub new_leaf: N,
+    pub path: MerkleProof<N>,
+}
+
+// A Merkle tree over a fixed power-of-two number of leaves which keeps every
+// level in its storage, so that single leaves can be updated in O(log n). By
+// default all levels are kept in memory, which never fails. Errors of other
+// storages are passed on to the caller.
+#[derive(Clone, Debug)]
+pub struct StoredMerkleTree<C: Compression, S: NodeStorage<C::Node> = MemoryStorage<<C as Compression>::Node>> {
+    compression: C,
+    storage: S,
+}
+
+impl<C: Compression> StoredMerkleTree<C> {
+    pub fn new(compression: C, leaves: Vec<C::Node>) -> Self {
+        assert!(leaves.len().is_power_of_two());
+        assert!(leaves.len() >= 2);
+        Self::build(compression, MemoryStorage::from_leaves(leaves)).unwrap()
+    }
+
+    // 2^depth zero leaves
+    pub fn with_depth(compression: C, depth: usize) -> Self {
+        let leaves = vec![compression.zero(); 1 << depth];
+        Self::new(compression, leaves)
+    }
+
+    pub fn leaves(&self) -> &[C::Node] {
+        self.storage.level(0)
+    }
+}
+
+impl<C: Compression, S: NodeStorage<C::Node>> StoredMerkleTree<C, S> {
+    // Computes all inner nodes from the leaves in the storage.
+    pub fn build(compression: C, storage: S) -> io::Result<Self> {
+        let mut tree = StoredMerkleTree { compression, storage };
+        for lv in 1..=tree.depth() {
+            for index in 0..1 << (tree.depth() - lv) {
+                tree.recompute(lv, index)?;
+            }
+        }
+        tree.flush()?;
+        Ok(tree)
+    }
+
+    // Takes the storage as it is, e.g., after reopening a file of a tree
+    // built before.
+    pub fn open(compression: C, storage: S) -> Self {
+        StoredMerkleTree { compression, storage }
+    }
+
+    pub fn compression_mut(&mut self) -> &mut C {
+        &mut self.compression
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.storage.flush()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.storage.depth()
+    }
+
+    pub fn num_leaves(&self) -> usize {
+        1 << self.depth()
+    }
+
+    pub fn root(&self) -> io::Result<C::Node> {
+        self.storage.get(self.depth(), 0)
+    }
+
+    pub fn leaf(&self, index: usize) -> io::Result<C::Node> {
+        self.storage.get(0, index)
+    }
+
+    pub fn node(&self, level: usize, index: usize) -> io::Result<C::Node> {
+        self.storage.get(level, index)
+    }
+
+    pub fn prove(&self, index: usize) -> io::Result<MerkleProof<C::Node>> {
+        assert!(index < self.num_leaves());
+        let siblings = (0..self.depth())
+            .map(|lv| self.storage.get(lv, (index >> lv) ^ 1))
+            .collect::<io::Result<_>>()?;
+        Ok(MerkleProof { siblings })
+    }
+
+    pub fn verify(&mut self, root: &C::Node, index: usize, leaf: &C::Node, proof: &MerkleProof<C::Node>) -> bool {
+        proof.siblings.len() == self.depth()
+            && index < self.num_leaves()
+            && climb(&mut self.compression, index, leaf, &proof.siblings) == *root
+    }
+
+    fn recompute(&mut self, level: usize, index: usize) -> io::Result<()> {
+        let left = self.storage.get(level - 1, 2 * index)?;
+        let right = self.storage.get(level - 1, 2 * index + 1)?;
+        let node = self.compression.compress(level - 1, index, &[&left, &right]);
+        self.storage.set(level, index, node)
+    }
+
+    pub fn update(&mut self, index: usize, value: C::Node) -> io::Result<C::Node> {
+        assert!(index < self.num_leaves());
+        self.storage.set(0, index, value)?;
+        for lv in 1..=self.depth() {
+            self.recompute(lv, index >> lv)?;
+        }
+        self.root()
+    }
+
+    // Later writes to the same index win. Every ancestor is recomputed once,
+    // no matter how many of the updated leaves lie below it.
+    pub fn batch_update(&mut self, updates: &[(usize, C::Node)]) -> io::Result<C::Node> {
+        let mut dirty: Vec<usize> = Vec::with_capacity(updates.len());
+        for (index, value) in updates {
+            assert!(*index < self.num_leaves());
+            self.storage.set(0, *index, value.to_owned())?;
+            dirty.push(*index);
+        }
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        for lv in 1..=self.depth() {
+            for d in dirty.iter_mut() {
+                *d >>= 1;
+            }
+            dirty.dedup();
+            for &index in dirty.iter() {
+                self.recompute(lv, index)?;
+            }
+        }
+        self.root()
+    }
+
+    // Applies the updates one after another and records a witness per write.
+    pub fn update_with_witnesses(&mut self, updates: &[(usize, C::Node)]) -> io::Result<Vec<UpdateWitness<C::Node>>> {
+        updates
+            .iter()
+            .map(|(index, value)| {
+                let witness = UpdateWitness {
+                    index: *index,
+                    old_leaf: self.leaf(*index)?,
+                    new_leaf: value.to_owned(),
+                    path: self.prove(*index)?,
+                };
+                self.update(*index, value.to_owned())?;
+                Ok(witness)
+            })
+            .collect()
+    }
+}
+
+// Replays the witnesses from `old_root` and checks that they end in `new_root`.
+pub fn verify_transition<C: Compression>(
+    compression: &mut C,
+    depth: usize,
+    old_root: &C::Node,
+    new_root: &C::Node,
+    witnesses: &[UpdateWitness<C::Node>],
+) -> bool {
+    let mut root = old_root.to_owned();
+    for w in witnesses {
+        if w.path.siblings.len() != depth || w.index >> depth != 0 {
+            return false;
+        }
+        if climb(compression, w.index, &w.old_leaf, &w.path.siblings) != root {
+            return false;
+        }
+        root = climb(compression, w.index, &w.new_leaf, &w.path.siblings);
+    }
+    root == *new_root
+}
+
+#[cfg(test)]
+mod stored_merkle_tree_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, utils::random_scalar};
+    use crate::merkle_tree::merkle_tree_fp::{FpCompression, MerkleTree};
+    use crate::merkle_tree::padding::Padding;
+    use crate::poseidon2::{poseidon2::Poseidon2, poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS};
+
+    type Scalar = FpBN256;
+
+    fn compression() -> FpCompression<Scalar, Poseidon2<Scalar>> {
+        FpCompression::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS))
+    }
+
+    #[test]
+    fn updates_match_rebuild() {
+        let leaves: Vec<Scalar> = (0..32).map(|_| random_scalar()).collect();
+        let mut tree = StoredMerkleTree::new(compression(), leaves.clone());
+        let mut mt = MerkleTree::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS));
+        assert_eq!(tree.root().unwrap(), mt.accumulate(&leaves));
+
+        let root = tree.update(5, random_scalar()).unwrap();
+        assert_eq!(root, mt.accumulate(tree.leaves()));
+
+        let updates: Vec<(usize, Scalar)> = vec![
+            (0, random_scalar()),
+            (1, random_scalar()),
+            (17, random_scalar()),
+            (31, random_scalar()),
+            (17, random_scalar()),
+        ];
+        let root = tree.batch_update(&updates).unwrap();
+        assert_eq!(tree.leaf(17).unwrap(), updates[4].1);
+        assert_eq!(root, mt.accumulate(tree.leaves()));
+
+        for i in [0, 5, 17, 31] {
+            let proof = tree.prove(i).unwrap();
+            let leaf = tree.leaf(i).unwrap();
+            assert!(tree.verify(&root, i, &leaf, &proof));
+        }
+    }
+
+    #[test]
+    fn with_depth() {
+        let mut tree = StoredMerkleTree::with_depth(compression(), 4);
+        let mut mt = MerkleTree::new(Poseidon2::new(&POSEIDON2_BN256_PARAMS));
+        assert_eq!(tree.root().unwrap(), mt.accumulate(&[Scalar::from(0u64); 16]));
+        let root = tree.batch_update(&[(3, Scalar::from(3u64)), (12, Scalar::from(12u64))]).unwrap();
+        assert_eq!(root, mt.accumulate(tree.leaves()));
+
+        let zero_padded = MerkleTree::with_padding(Poseidon2::new(&POSEIDON2_BN256_PARAMS), Padding::Zero);
+        let stored = zero_padded.build_stored(&tree.leaves()[..13]);
+        assert_eq!(stored.num_leaves(), 16);
+        assert_eq!(stored.root().unwrap(), root);
+    }
+
+    #[test]
+    fn transition_witnesses() {
+        let leaves: Vec<Scalar> = (0..16).map(|_| random_scalar()).collect();
+        let mut tree = StoredMerkleTree::new(compression(), leaves);
+        let old_root = tree.root().unwrap();
+
+        let updates: Vec<(usize, Scalar)> = vec![
+            (3, random_scalar()),
+            (2, random_scalar()),
+            (3, random_scalar()),
+            (15, random_scalar()),
+        ];
+        let witnesses = tree.update_with_witnesses(&updates).unwrap();
+        let new_root = tree.root().unwrap();
+        assert_eq!(witnesses.len(), 4);
+        assert_eq!(witnesses[2].old_leaf, updates[0].1);
+
+        let mut c = compression();
+        assert!(verify_transition(&mut c, 4, &old_root, &new_root, &witnesses));
+        assert!(!verify_transition(&mut c, 4, &new_root, &old_root, &witnesses));
+
+        let mut reordered = witnesses.clone();
+        reordered.swap(0, 1);
+        assert!(!verify_transition(&mut c, 4, &old_root, &new_root, &reordered));
+
+        let mut tampered = witnesses;
+        tampered[3].new_leaf = random_scalar();
+        assert!(!verify_transition(&mut c, 4, &old_root, &new_root, &tampered));
+    }
+}
@@ -253,11 +253,11 @@ impl<S: PrimeField> Neptune<S> {
 }
 
 impl<S: PrimeField> MerkleTreeHash<S> for Neptune<S> {
-    fn compress(&self, input: &[&S]) -> S {
+    fn compress_tagged(&self, input: &[&S], tag: &S) -> S {
         self.permutation(&[
             input[0].to_owned(),
             input[1].to_owned(),
-            S::zero(),
+            tag.to_owned(),
             S::zero(),
         ])[0]
     }
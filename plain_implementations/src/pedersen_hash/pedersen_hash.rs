@@ -0,0 +1,244 @@
+use blake2::{
+    digest::core_api::{Buffer, UpdateCore, VariableOutputCore},
+    Blake2sVarCore,
+};
+use jubjub::{AffinePoint, Base, ExtendedNielsPoint, ExtendedPoint, Fr};
+use lazy_static::lazy_static;
+
+use crate::merkle_tree::merkle_tree_sapling::MerkleTreeHash;
+use crate::utils::{i2lebsp_bytes, i2lebsp_u64};
+
+// The Sapling Pedersen hash as specified in the Zcash protocol specification,
+// Section 5.4.1.7. Messages are padded to a multiple of 3 bits and split into
+// segments of C chunks, every segment is encoded as a scalar and multiplied
+// with its own generator I_i.
+
+// chunks per segment
+pub const C: usize = 63;
+pub const NUM_GENERATORS: usize = 6;
+// bits of the scalar covered by one table lookup
+pub const WINDOW: usize = 8;
+const NUM_WINDOWS: usize = 256 / WINDOW;
+
+const PERSONALIZATION: &[u8; 8] = b"Zcash_PH";
+const URS: &[u8; 64] = b"096b36a5804bfacef1691e173c366a47ff5ba84a44f26ddd7e8d9f79d5b42df0";
+pub const MERKLE_DEPTH_SAPLING: usize = 32;
+
+lazy_static! {
+    // I_i = FindGroupHash("Zcash_PH", I2LEOSP_32(i - 1))
+    static ref GENERATORS: Vec<ExtendedPoint> = (0..NUM_GENERATORS as u32)
+        .map(|i| find_group_hash(PERSONALIZATION, &i.to_le_bytes()))
+        .collect();
+    // TABLES[i][k][j] = [j * 2^(WINDOW * k)] I_i
+    static ref TABLES: Vec<Vec<Vec<ExtendedNielsPoint>>> = GENERATORS.iter().map(window_table).collect();
+}
+
+fn blake2s_personal(personalization: &[u8; 8], input: &[&[u8]]) -> [u8; 32] {
+    let mut core = Blake2sVarCore::new_with_params(&[], personalization, 0, 32);
+    let mut buffer = Buffer::<Blake2sVarCore>::default();
+    for chunk in input {
+        buffer.digest_blocks(chunk, |blocks| core.update_blocks(blocks));
+    }
+    let mut out = Default::default();
+    core.finalize_variable_core(&mut buffer, &mut out);
+    out.into()
+}
+
+// GroupHash^J, None for the failure result
+pub fn group_hash(personalization: &[u8; 8], message: &[u8]) -> Option<ExtendedPoint> {
+    let hash = blake2s_personal(personalization, &[URS, message]);
+    let point: Option<AffinePoint> = AffinePoint::from_bytes(hash).into();
+    let point = ExtendedPoint::from(point?).mul_by_cofactor();
+    if bool::from(point.is_identity()) {
+        None
+    } else {
+        Some(point)
+    }
+}
+
+pub fn find_group_hash(personalization: &[u8; 8], message: &[u8]) -> ExtendedPoint {
+    let mut input = message.to_vec();
+    input.push(0);
+    for i in 0..=255u8 {
+        *input.last_mut().unwrap() = i;
+        if let Some(point) = group_hash(personalization, &input) {
+            return point;
+        }
+    }
+    panic!("no group hash found");
+}
+
+fn window_table(generator: &ExtendedPoint) -> Vec<Vec<ExtendedNielsPoint>> {
+    let mut base = *generator;
+    let mut table = Vec::with_capacity(NUM_WINDOWS);
+    for _ in 0..NUM_WINDOWS {
+        let mut acc = ExtendedPoint::identity();
+        let mut row = Vec::with_capacity(1 << WINDOW);
+        for _ in 0..1 << WINDOW {
+            row.push(acc.to_niels());
+            acc += &base;
+        }
+        table.push(row);
+        base = acc;
+    }
+    table
+}
+
+// I2LEBSP_l(x) of the little-endian encoding of x
+pub fn i2lebsp(l: usize, x: &Base) -> Vec<bool> {
+    i2lebsp_bytes(l, &x.to_bytes())
+}
+
+// Extract_J, the u-coordinate
+pub fn extract_j(point: &ExtendedPoint) -> Base {
+    AffinePoint::from(point).get_u()
+}
+
+// <M_i> = sum_j enc(m_j) * 2^(4 (j - 1)) with enc(s0, s1, s2) = (1 - 2 s2) (1 + s0 + 2 s1)
+pub fn encode_segment(segment: &[bool]) -> Fr {
+    let mut scalar = Fr::zero();
+    let mut shift = Fr::one();
+    for chunk in segment.chunks(3) {
+        let bit = |i: usize| chunk.get(i).cloned().unwrap_or(false);
+        let mut enc = Fr::from(1 + bit(0) as u64 + 2 * bit(1) as u64);
+        if bit(2) {
+            enc = -enc;
+        }
+        scalar += enc * shift;
+        shift = shift.double().double().double().double();
+    }
+    scalar
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PedersenHash {}
+
+impl PedersenHash {
+    pub fn new() -> Self {
+        PedersenHash {}
+    }
+
+    pub fn generator(i: usize) -> ExtendedPoint {
+        GENERATORS[i]
+    }
+
+    // [scalar] I_i using the precomputed window table of the generator
+    fn mul_generator(i: usize, scalar: &Fr) -> ExtendedPoint {
+        let table = &TABLES[i];
+        let bytes = scalar.to_bytes();
+        let mut acc = ExtendedPoint::identity();
+        for (k, row) in table.iter().enumerate() {
+            let window = (0..WINDOW).fold(0usize, |w, j| {
+                let bit = k * WINDOW + j;
+                w | ((((bytes[bit / 8] >> (bit % 8)) & 1) as usize) << j)
+            });
+            acc += &row[window];
+        }
+        acc
+    }
+
+    pub fn hash_to_point(&self, message: &[bool]) -> ExtendedPoint {
+        let segments = message.chunks(3 * C);
+        assert!(segments.len() <= NUM_GENERATORS, "message too long");
+        segments
+            .enumerate()
+            .fold(ExtendedPoint::identity(), |acc, (i, segment)| {
+                acc + Self::mul_generator(i, &encode_segment(segment))
+            })
+    }
+
+    pub fn hash(&self, message: &[bool]) -> Base {
+        extract_j(&self.hash_to_point(message))
+    }
+}
+
+// MerkleCRH^Sapling: PedersenHash of the personalization I2LEBSP_6(level) ||
+// I2LEBSP_255(left) || I2LEBSP_255(right), where level is the height of the
+// inputs (0 for leaves), i.e., MerkleDepth^Sapling - 1 - layer.
+#[derive(Clone, Debug, Default)]
+pub struct SaplingMerkleCrh {
+    hasher: PedersenHash,
+}
+
+impl SaplingMerkleCrh {
+    pub fn new() -> Self {
+        SaplingMerkleCrh {
+            hasher: PedersenHash::new(),
+        }
+    }
+
+    // value of an unused leaf
+    pub fn uncommitted() -> Base {
+        Base::one()
+    }
+
+    // roots of the empty trees of height 0 to `depth`
+    pub fn empty_roots(&self, depth: usize) -> Vec<Base> {
+        let mut roots = vec![Self::uncommitted()];
+        for level in 0..depth {
+            let last = roots[level];
+            roots.push(self.compress(level, &[&last, &last]));
+        }
+        roots
+    }
+}
+
+impl MerkleTreeHash for SaplingMerkleCrh {
+    fn compress(&self, level: usize, input: &[&Base; 2]) -> Base {
+        let mut message = i2lebsp_u64(6, level as u64);
+        message.extend(i2lebsp(255, input[0]));
+        message.extend(i2lebsp(255, input[1]));
+        self.hasher.hash(&message)
+    }
+}
+
+#[cfg(test)]
+mod pedersen_hash_tests {
+    use super::*;
+    use crate::merkle_tree::merkle_tree_sapling::MerkleTree;
+    use group_ped::ff::Field;
+
+    fn from_le_hex(s: &str) -> Base {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&hex::decode(s).unwrap());
+        Base::from_bytes(&bytes).unwrap()
+    }
+
+    #[test]
+    fn sapling_empty_root() {
+        let crh = SaplingMerkleCrh::new();
+        let roots = crh.empty_roots(MERKLE_DEPTH_SAPLING);
+        assert_eq!(
+            roots[MERKLE_DEPTH_SAPLING],
+            from_le_hex("fbc2f4300c01f0b7820d00e3347c8da4ee614674376cbc45359daa54f9b5493e")
+        );
+
+        let mut mt = MerkleTree::new(crh);
+        let leaves = vec![SaplingMerkleCrh::uncommitted(); 8];
+        assert_eq!(mt.accumulate(&leaves), roots[3]);
+    }
+
+    #[test]
+    fn window_tables() {
+        let hasher = PedersenHash::new();
+        let message: Vec<bool> = (0..3 * C + 100).map(|_| random::random()).collect();
+        let expected = PedersenHash::generator(0) * encode_segment(&message[..3 * C])
+            + PedersenHash::generator(1) * encode_segment(&message[3 * C..]);
+        assert_eq!(hasher.hash_to_point(&message), expected);
+
+        let scalar = Fr::random(ark_std::rand::thread_rng());
+        assert_eq!(PedersenHash::mul_generator(5, &scalar), PedersenHash::generator(5) * scalar);
+    }
+
+    #[test]
+    fn chunk_padding() {
+        // messages are padded with zero bits to a multiple of 3 bits
+        let hasher = PedersenHash::new();
+        let message = vec![true, false, true, true];
+        let mut padded = message.clone();
+        padded.resize(6, false);
+        assert_eq!(hasher.hash(&message), hasher.hash(&padded));
+        assert_eq!(encode_segment(&[false, false, true]), -Fr::one());
+        assert_eq!(encode_segment(&[true, true, false, false, false, false]), Fr::from(20u64));
+    }
+}
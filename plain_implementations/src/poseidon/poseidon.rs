@@ -158,8 +158,8 @@ impl<S: PrimeField> Poseidon<S> {
 }
 
 impl<F: PrimeField> MerkleTreeHash<F> for Poseidon<F> {
-    fn compress(&self, input: &[&F]) -> F {
-        self.permutation(&[input[0].to_owned(), input[1].to_owned(), F::zero()])[0]
+    fn compress_tagged(&self, input: &[&F], tag: &F) -> F {
+        self.permutation(&[input[0].to_owned(), input[1].to_owned(), tag.to_owned()])[0]
     }
 }
 
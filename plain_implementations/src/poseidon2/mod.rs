@@ -1,6 +1,9 @@
 #[allow(clippy::module_inception)]
 pub mod poseidon2;
+pub mod poseidon2_compression;
+pub mod poseidon2_digest;
 pub mod poseidon2_params;
+pub mod poseidon2_tree_hash;
 pub mod poseidon2_instance_goldilocks;
 pub mod poseidon2_instance_babybear;
 pub mod poseidon2_instance_bls12;
@@ -1,5 +1,5 @@
 use super::poseidon2_params::Poseidon2Params;
-use crate::merkle_tree::merkle_tree_fp::MerkleTreeHash;
+use crate::merkle_tree::merkle_tree_fp::{tag_bit, MerkleTreeHash, SpongeHash};
 use ark_ff::PrimeField;
 use std::sync::Arc;
 
@@ -49,6 +49,58 @@ impl<F: PrimeField> Poseidon2<F> {
         current_state
     }
 
+    // Sponge whose capacity consists of the last t - rate state elements, with
+    // the last one initialized with `iv`. Blocks of `rate` elements, the last
+    // one padded with zeros, are added to the state, each followed by a
+    // permutation (one permutation for the empty input). The output is
+    // squeezed `rate` elements at a time.
+    pub fn sponge(&self, input: &[F], iv: F, rate: usize, out_len: usize) -> Vec<F> {
+        let t = self.params.t;
+        assert!(rate >= 1 && rate < t);
+
+        let mut state = vec![F::zero(); t];
+        state[t - 1] = iv;
+        for chunk in input.chunks(rate) {
+            for (s, el) in state.iter_mut().zip(chunk.iter()) {
+                s.add_assign(el);
+            }
+            state = self.permutation(&state);
+        }
+        if input.is_empty() {
+            state = self.permutation(&state);
+        }
+
+        let mut out = Vec::with_capacity(out_len);
+        loop {
+            out.extend_from_slice(&state[..rate.min(out_len - out.len())]);
+            if out.len() == out_len {
+                break;
+            }
+            state = self.permutation(&state);
+        }
+        out
+    }
+
+    // Sponge with the capacity initialized with the input length, so no
+    // further padding is required. Lengths stay below 2^b, the bit set in the
+    // capacity of every Merkle tree compression (merkle_tree_fp::tag_bit).
+    pub fn hash_many_with_rate(&self, input: &[F], rate: usize, out_len: usize) -> Vec<F> {
+        assert!((input.len() as u64) < 1 << tag_bit::<F>(), "input too long");
+        self.sponge(input, F::from(input.len() as u64), rate, out_len)
+    }
+
+    // The capacity holds out_len elements (at most t-1), so it is never
+    // narrower than a digest of out_len elements.
+    pub fn hash_many(&self, input: &[F], out_len: usize) -> Vec<F> {
+        let t = self.params.t;
+        assert!(t >= 2);
+        self.hash_many_with_rate(input, t - out_len.clamp(1, t - 1), out_len)
+    }
+
+    pub fn hash(&self, input: &[F]) -> F {
+        self.hash_many(input, 1)[0]
+    }
+
     fn sbox(&self, input: &[F]) -> Vec<F> {
         input.iter().map(|el| self.sbox_p(el)).collect()
     }
@@ -221,8 +273,29 @@ impl<F: PrimeField> Poseidon2<F> {
 }
 
 impl<F: PrimeField> MerkleTreeHash<F> for Poseidon2<F> {
-    fn compress(&self, input: &[&F]) -> F {
-        self.permutation(&[input[0].to_owned(), input[1].to_owned(), F::zero()])[0]
+    // [a, b, tag, 0, ..., 0] for any t >= 3. With t = 2 the input fills the
+    // whole state, so the output is fed forward, P([a, b])[0] + a, to make it
+    // one-way, and the tag is added to it.
+    fn compress_tagged(&self, input: &[&F], tag: &F) -> F {
+        let t = self.params.t;
+        let mut state = vec![F::zero(); t];
+        state[0] = input[0].to_owned();
+        state[1] = input[1].to_owned();
+        if t >= 3 {
+            state[2] = tag.to_owned();
+            return self.permutation(&state)[0];
+        }
+        self.permutation(&state)[0] + input[0] + tag
+    }
+}
+
+impl<F: PrimeField> SpongeHash<F> for Poseidon2<F> {
+    fn hash(&self, input: &[F]) -> F {
+        Poseidon2::hash(self, input)
+    }
+
+    fn hash_tagged(&self, input: &[F], tag: &F) -> F {
+        self.sponge(input, tag.to_owned(), self.params.t - 1, 1)[0]
     }
 }
 
@@ -502,6 +575,24 @@ mod poseidon2_tests_bn256 {
         assert_eq!(perm[2], from_hex("0x1ed25194542b12eef8617361c3ba7c52e660b145994427cc86296242cf766ec8"));
 
     }
+
+    #[test]
+    fn sponge() {
+        let poseidon2 = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let input: Vec<Scalar> = (0..5).map(|_| random_scalar()).collect();
+
+        let mut padded = input.clone();
+        padded.push(Scalar::from(0u64));
+        assert_ne!(poseidon2.hash(&input), poseidon2.hash(&padded));
+        assert_ne!(poseidon2.hash(&[]), poseidon2.hash(&[Scalar::from(0u64)]));
+
+        let out = poseidon2.hash_many(&input, 5);
+        assert_eq!(out.len(), 5);
+        assert_eq!(poseidon2.hash(&input), poseidon2.hash_many_with_rate(&input, 2, 1)[0]);
+        // two or more outputs leave rate 1 for t = 3
+        assert_eq!(out, poseidon2.hash_many_with_rate(&input, 1, 5));
+        assert_eq!(out[..2], poseidon2.hash_many(&input, 2)[..]);
+    }
 }
 
 #[allow(unused_imports)]
@@ -0,0 +1,206 @@
+use super::poseidon2::Poseidon2;
+use crate::merkle_tree::merkle_tree_fp::{MerkleTreeHash, SpongeHash};
+use ark_ff::PrimeField;
+
+// How the output of a compression is derived from the permutation P applied to
+// the padded input x of width t, for an output of n elements:
+//  - Truncate:    P(x)[0..n], which is invertible unless x is padded and
+//                 therefore refused for inputs filling the whole state
+//  - FeedForward: (P(x) + x)[0..n]
+//  - Jive:        sum of the t/n blocks of n elements of P(x) + x, which
+//                 requires n to divide t
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompressionMode {
+    Truncate,
+    #[default]
+    FeedForward,
+    Jive,
+}
+
+#[derive(Clone, Debug)]
+pub struct Poseidon2Compression<F: PrimeField> {
+    perm: Poseidon2<F>,
+    mode: CompressionMode,
+}
+
+impl<F: PrimeField> Poseidon2Compression<F> {
+    pub fn new(perm: Poseidon2<F>, mode: CompressionMode) -> Self {
+        Poseidon2Compression { perm, mode }
+    }
+
+    pub fn perm(&self) -> &Poseidon2<F> {
+        &self.perm
+    }
+
+    pub fn mode(&self) -> CompressionMode {
+        self.mode
+    }
+
+    pub fn get_t(&self) -> usize {
+        self.perm.get_t()
+    }
+
+    // Compresses the input, padded with zeros to width t, to out_len elements.
+    pub fn compress_to(&self, input: &[F], out_len: usize) -> Vec<F> {
+        assert!(
+            self.mode != CompressionMode::Truncate || input.len() < self.perm.get_t(),
+            "truncation of a full state is invertible"
+        );
+        self.reduce(input, out_len)
+    }
+
+    fn reduce(&self, input: &[F], out_len: usize) -> Vec<F> {
+        let t = self.perm.get_t();
+        assert!(input.len() <= t);
+        assert!(out_len >= 1 && out_len <= t);
+
+        let mut state = input.to_owned();
+        state.resize(t, F::zero());
+        let mut out = self.perm.permutation(&state);
+        if self.mode == CompressionMode::Truncate {
+            out.truncate(out_len);
+            return out;
+        }
+
+        for (o, x) in out.iter_mut().zip(state.iter()) {
+            o.add_assign(x);
+        }
+        if self.mode == CompressionMode::Jive {
+            assert_eq!(t % out_len, 0, "Jive needs the output length to divide t");
+            for i in out_len..t {
+                let el = out[i];
+                out[i % out_len].add_assign(&el);
+            }
+        }
+        out.truncate(out_len);
+        out
+    }
+}
+
+// [a, b, tag, 0, ..., 0] compressed to a single element, where the tag is a
+// fixed capacity element even for truncation. Instances with t = 2 have no room
+// for a tag, which is added to the output instead, i.e., it joins the
+// feed-forward term.
+impl<F: PrimeField> MerkleTreeHash<F> for Poseidon2Compression<F> {
+    fn compress_tagged(&self, input: &[&F], tag: &F) -> F {
+        if self.perm.get_t() >= 3 {
+            return self.reduce(&[input[0].to_owned(), input[1].to_owned(), tag.to_owned()], 1)[0];
+        }
+        self.compress_to(&[input[0].to_owned(), input[1].to_owned()], 1)[0] + tag
+    }
+}
+
+// the sponge of the underlying permutation
+impl<F: PrimeField> SpongeHash<F> for Poseidon2Compression<F> {
+    fn hash(&self, input: &[F]) -> F {
+        self.perm.hash(input)
+    }
+
+    fn hash_tagged(&self, input: &[F], tag: &F) -> F {
+        SpongeHash::hash_tagged(&self.perm, input, tag)
+    }
+}
+
+#[cfg(test)]
+mod poseidon2_compression_tests {
+    use super::*;
+    use crate::fields::{bls12::FpBLS12, goldilocks::FpGoldiLocks, utils::random_scalar};
+    use crate::fields::conversions::ark_to_jubjub;
+    use crate::merkle_tree::{merkle_tree_fp, merkle_tree_multi, merkle_tree_sapling, mmcs::Mmcs};
+    use crate::poseidon2::poseidon2_instance_bls12::{POSEIDON2_BLS_2_PARAMS, POSEIDON2_BLS_3_PARAMS};
+    use crate::poseidon2::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+
+    type Scalar = FpBLS12;
+
+    const MODES: [CompressionMode; 3] = [
+        CompressionMode::Truncate,
+        CompressionMode::FeedForward,
+        CompressionMode::Jive,
+    ];
+
+    #[test]
+    fn modes_t2() {
+        let perm = Poseidon2::new(&POSEIDON2_BLS_2_PARAMS);
+        let (a, b): (Scalar, Scalar) = (random_scalar(), random_scalar());
+        let p = perm.permutation(&[a, b]);
+
+        let expected = [p[0] + a, p[0] + p[1] + a + b];
+        for (mode, expected) in MODES[1..].iter().zip(expected.iter()) {
+            let c = Poseidon2Compression::new(perm.clone(), *mode);
+            assert_eq!(c.compress(&[&a, &b]), *expected);
+        }
+        assert_eq!(Poseidon2Compression::new(perm.clone(), CompressionMode::default()).compress(&[&a, &b]), p[0] + a);
+        assert_eq!(perm.compress(&[&a, &b]), p[0] + a);
+    }
+
+    #[test]
+    #[should_panic]
+    fn truncation_t2() {
+        let c = Poseidon2Compression::new(Poseidon2::new(&POSEIDON2_BLS_2_PARAMS), CompressionMode::Truncate);
+        c.compress(&[&Scalar::from(1u64), &Scalar::from(2u64)]);
+    }
+
+    #[test]
+    fn merkle_trees_t2() {
+        let set: Vec<Scalar> = (0..8).map(|_| random_scalar()).collect();
+        let mut roots = vec![];
+        for mode in MODES[1..].iter().copied() {
+            let c = Poseidon2Compression::new(Poseidon2::new(&POSEIDON2_BLS_2_PARAMS), mode);
+            let mut mt = merkle_tree_fp::MerkleTree::new(c.clone());
+            let root = mt.accumulate(&set);
+            let tree = mt.build_with_cap(&set, 0);
+            assert_eq!(mt.climb(5, &set[5], &tree.prove(5).siblings), root);
+            let mut legacy = merkle_tree_fp::MerkleTree::new_legacy(c);
+            assert_ne!(legacy.accumulate(&set), root);
+            roots.push(root);
+        }
+        assert_ne!(roots[0], roots[1]);
+        let mut plain = merkle_tree_fp::MerkleTree::new(Poseidon2::new(&POSEIDON2_BLS_2_PARAMS));
+        assert_eq!(plain.accumulate(&set), roots[0]);
+
+        // without a capacity element the tag is added to the output
+        let c = Poseidon2Compression::new(Poseidon2::new(&POSEIDON2_BLS_2_PARAMS), CompressionMode::FeedForward);
+        let tag = merkle_tree_fp::node_tag(3, None);
+        assert_eq!(c.compress_tagged(&[&set[0], &set[1]], &tag), c.compress(&[&set[0], &set[1]]) + tag);
+        let mut fp = merkle_tree_fp::MerkleTree::new(c.clone());
+        let mut sapling = merkle_tree_sapling::MerkleTree::new(c);
+        let leaves: Vec<_> = set.iter().map(ark_to_jubjub).collect();
+        assert_eq!(sapling.accumulate(&leaves), ark_to_jubjub(&fp.accumulate(&set)));
+
+        // t = 3 instances are tagged as before
+        let c = Poseidon2Compression::new(Poseidon2::new(&POSEIDON2_BLS_3_PARAMS), CompressionMode::Truncate);
+        let mut tagged = merkle_tree_fp::MerkleTree::new(c);
+        let mut plain = merkle_tree_fp::MerkleTree::new(Poseidon2::new(&POSEIDON2_BLS_3_PARAMS));
+        assert_eq!(tagged.accumulate(&set), plain.accumulate(&set));
+    }
+
+    #[test]
+    fn multi_element_modes() {
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        let x: Vec<FpGoldiLocks> = (0..8).map(|_| random_scalar()).collect();
+        let p = perm.permutation(&x);
+
+        let jive = Poseidon2Compression::new(perm.clone(), CompressionMode::Jive).compress_to(&x, 4);
+        let expected: Vec<FpGoldiLocks> = (0..4).map(|j| p[j] + x[j] + p[j + 4] + x[j + 4]).collect();
+        assert_eq!(jive, expected);
+
+        let set: Vec<[FpGoldiLocks; 4]> = x.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+        // truncation is rejected for t = 2N
+        for mode in MODES[1..].iter().copied() {
+            let mut mt = merkle_tree_multi::MerkleTree::<FpGoldiLocks, 4>::with_mode(perm.clone(), mode);
+            let c = Poseidon2Compression::new(perm.clone(), mode);
+            assert_eq!(mt.accumulate(&set).to_vec(), c.compress_to(&x, 4));
+        }
+    }
+
+    #[test]
+    fn generic_trees() {
+        let c = Poseidon2Compression::new(Poseidon2::new(&POSEIDON2_BLS_2_PARAMS), CompressionMode::Jive);
+        let mmcs = Mmcs::new(c);
+        let matrix: Vec<Vec<Scalar>> = (0..4).map(|_| vec![random_scalar(), random_scalar()]).collect();
+        let (root, data) = mmcs.commit(vec![matrix]);
+        let opening = mmcs.open(2, &data);
+        assert!(mmcs.verify(&root, &data.dimensions(), 2, &opening));
+        assert!(!mmcs.verify(&root, &data.dimensions(), 3, &opening));
+    }
+}
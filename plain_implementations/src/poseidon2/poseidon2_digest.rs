@@ -0,0 +1,356 @@
+use ark_ff::PrimeField;
+use sha2::digest::{
+    generic_array::ArrayLength,
+    typenum::U32,
+    FixedOutput, FixedOutputReset, HashMarker, Output, OutputSizeUser, Reset, Update,
+};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use super::poseidon2::Poseidon2;
+use super::poseidon2_instance_babybear::POSEIDON2_BABYBEAR_16_PARAMS;
+use super::poseidon2_instance_bls12::POSEIDON2_BLS_3_PARAMS;
+use super::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+use super::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+use super::poseidon2_instance_pallas::POSEIDON2_PALLAS_3_PARAMS;
+use super::poseidon2_params::Poseidon2Params;
+use crate::fields::{
+    babybear::FpBabyBear,
+    bls12::FpBLS12,
+    bn256::FpBN256,
+    goldilocks::FpGoldiLocks,
+    pallas::FpPallas,
+    utils::{bytes_per_element, canonical_byte_len, pack_bytes, to_canonical_bytes},
+};
+
+// Byte-oriented Poseidon2 hash implementing the `digest` traits, so it can be
+// used wherever a `Digest` is expected, e.g., in the F2 Merkle tree.
+//
+// The message is split into chunks of bytes_per_element bytes (31 for BN256,
+// 7 for Goldilocks), each read as a little-endian integer, with a shorter last
+// chunk. The byte length of the message is appended as a final element, which
+// makes the element sequence injective. The elements are absorbed by a sponge
+// with a capacity of OUT_ELEMENTS elements and rate t - OUT_ELEMENTS, so the
+// capacity is as large as the output, e.g., 4 Goldilocks or 8 BabyBear
+// elements. The last partial block is zero padded (the sequence always ends
+// with the length, so no two messages absorb to the same blocks). The output
+// consists of OUT_ELEMENTS squeezed elements in their canonical little-endian
+// encoding.
+pub trait Poseidon2DigestInstance {
+    type F: PrimeField;
+    type OutputSize: ArrayLength<u8> + 'static;
+    const OUT_ELEMENTS: usize;
+
+    fn params() -> &'static Arc<Poseidon2Params<Self::F>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Poseidon2Digest<I: Poseidon2DigestInstance> {
+    perm: Poseidon2<I::F>,
+    state: Vec<I::F>,
+    // number of elements absorbed into the current block
+    pos: usize,
+    // bytes not yet packed into an element
+    pending: Vec<u8>,
+    len: u64,
+    instance: PhantomData<I>,
+}
+
+impl<I: Poseidon2DigestInstance> Default for Poseidon2Digest<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<I: Poseidon2DigestInstance> Poseidon2Digest<I> {
+    pub fn new() -> Self {
+        let perm = Poseidon2::new(I::params());
+        let t = perm.get_t();
+        assert!(t > I::OUT_ELEMENTS, "permutation too narrow for the capacity");
+        assert_eq!(
+            I::OUT_ELEMENTS * canonical_byte_len::<I::F>(),
+            <I::OutputSize as sha2::digest::typenum::Unsigned>::USIZE
+        );
+        Poseidon2Digest {
+            perm,
+            state: vec![I::F::from(0u64); t],
+            pos: 0,
+            pending: Vec::with_capacity(bytes_per_element::<I::F>()),
+            len: 0,
+            instance: PhantomData,
+        }
+    }
+
+    pub fn capacity() -> usize {
+        I::OUT_ELEMENTS
+    }
+
+    pub fn rate() -> usize {
+        I::params().t - Self::capacity()
+    }
+
+    fn absorb(&mut self, el: I::F) {
+        self.state[self.pos] += el;
+        self.pos += 1;
+        if self.pos == Self::rate() {
+            self.state = self.perm.permutation(&self.state);
+            self.pos = 0;
+        }
+    }
+
+    fn squeeze(mut self) -> Vec<I::F> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.absorb(pack_bytes(&pending)[0]);
+        }
+        self.absorb(I::F::from(self.len));
+        if self.pos != 0 {
+            self.state = self.perm.permutation(&self.state);
+        }
+
+        let rate = Self::rate();
+        let mut out = Vec::with_capacity(I::OUT_ELEMENTS);
+        loop {
+            out.extend_from_slice(&self.state[..rate.min(I::OUT_ELEMENTS - out.len())]);
+            if out.len() == I::OUT_ELEMENTS {
+                break;
+            }
+            self.state = self.perm.permutation(&self.state);
+        }
+        out
+    }
+}
+
+impl<I: Poseidon2DigestInstance> HashMarker for Poseidon2Digest<I> {}
+
+impl<I: Poseidon2DigestInstance> OutputSizeUser for Poseidon2Digest<I> {
+    type OutputSize = I::OutputSize;
+}
+
+impl<I: Poseidon2DigestInstance> Update for Poseidon2Digest<I> {
+    fn update(&mut self, data: &[u8]) {
+        let chunk_len = bytes_per_element::<I::F>();
+        self.len += data.len() as u64;
+
+        let mut data = data;
+        if !self.pending.is_empty() {
+            let take = (chunk_len - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < chunk_len {
+                return;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.absorb(pack_bytes(&pending)[0]);
+        }
+
+        let full = data.len() - data.len() % chunk_len;
+        for el in pack_bytes(&data[..full]) {
+            self.absorb(el);
+        }
+        self.pending.extend_from_slice(&data[full..]);
+    }
+}
+
+impl<I: Poseidon2DigestInstance> FixedOutput for Poseidon2Digest<I> {
+    fn finalize_into(self, out: &mut Output<Self>) {
+        let n = canonical_byte_len::<I::F>();
+        for (chunk, el) in out.chunks_mut(n).zip(self.squeeze()) {
+            chunk.copy_from_slice(&to_canonical_bytes(&el));
+        }
+    }
+}
+
+impl<I: Poseidon2DigestInstance> Reset for Poseidon2Digest<I> {
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<I: Poseidon2DigestInstance> FixedOutputReset for Poseidon2Digest<I> {
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        std::mem::take(self).finalize_into(out);
+    }
+}
+
+impl<I: Poseidon2DigestInstance> io::Write for Poseidon2Digest<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Update::update(self, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bn256Instance;
+
+impl Poseidon2DigestInstance for Bn256Instance {
+    type F = FpBN256;
+    type OutputSize = U32;
+    const OUT_ELEMENTS: usize = 1;
+
+    fn params() -> &'static Arc<Poseidon2Params<FpBN256>> {
+        &POSEIDON2_BN256_PARAMS
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Bls12Instance;
+
+impl Poseidon2DigestInstance for Bls12Instance {
+    type F = FpBLS12;
+    type OutputSize = U32;
+    const OUT_ELEMENTS: usize = 1;
+
+    fn params() -> &'static Arc<Poseidon2Params<FpBLS12>> {
+        &POSEIDON2_BLS_3_PARAMS
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PallasInstance;
+
+impl Poseidon2DigestInstance for PallasInstance {
+    type F = FpPallas;
+    type OutputSize = U32;
+    const OUT_ELEMENTS: usize = 1;
+
+    fn params() -> &'static Arc<Poseidon2Params<FpPallas>> {
+        &POSEIDON2_PALLAS_3_PARAMS
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GoldilocksInstance;
+
+impl Poseidon2DigestInstance for GoldilocksInstance {
+    type F = FpGoldiLocks;
+    type OutputSize = U32;
+    const OUT_ELEMENTS: usize = 4;
+
+    fn params() -> &'static Arc<Poseidon2Params<FpGoldiLocks>> {
+        &POSEIDON2_GOLDILOCKS_8_PARAMS
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BabyBearInstance;
+
+impl Poseidon2DigestInstance for BabyBearInstance {
+    type F = FpBabyBear;
+    type OutputSize = U32;
+    const OUT_ELEMENTS: usize = 8;
+
+    fn params() -> &'static Arc<Poseidon2Params<FpBabyBear>> {
+        &POSEIDON2_BABYBEAR_16_PARAMS
+    }
+}
+
+pub type Poseidon2Bn256 = Poseidon2Digest<Bn256Instance>;
+pub type Poseidon2Bls12 = Poseidon2Digest<Bls12Instance>;
+pub type Poseidon2Pallas = Poseidon2Digest<PallasInstance>;
+pub type Poseidon2Goldilocks = Poseidon2Digest<GoldilocksInstance>;
+pub type Poseidon2BabyBear = Poseidon2Digest<BabyBearInstance>;
+
+#[cfg(test)]
+mod poseidon2_digest_tests {
+    use super::*;
+    use crate::fields::utils::from_canonical_bytes;
+    use crate::merkle_tree::merkle_tree_f2::MerkleTree;
+    use sha2::Digest;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        (0..len).map(|_| random::random()).collect()
+    }
+
+    #[test]
+    fn matches_sponge() {
+        // 31 bytes per element and the length: [m0, m1, m2, 70] with rate 2
+        let message = random_bytes(70);
+        let mut elements: Vec<FpBN256> = pack_bytes(&message);
+        elements.push(FpBN256::from(70u64));
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let mut state = vec![FpBN256::from(0u64); 3];
+        for chunk in elements.chunks(2) {
+            for (s, el) in state.iter_mut().zip(chunk) {
+                *s += el;
+            }
+            state = perm.permutation(&state);
+        }
+        let digest = Poseidon2Bn256::digest(&message);
+        assert_eq!(from_canonical_bytes::<FpBN256>(&digest), Some(state[0]));
+    }
+
+    #[test]
+    fn capacity_covers_output() {
+        assert_eq!((Poseidon2Bn256::rate(), Poseidon2Bn256::capacity()), (2, 1));
+        assert_eq!((Poseidon2Bls12::rate(), Poseidon2Bls12::capacity()), (2, 1));
+        assert_eq!((Poseidon2Pallas::rate(), Poseidon2Pallas::capacity()), (2, 1));
+        assert_eq!((Poseidon2Goldilocks::rate(), Poseidon2Goldilocks::capacity()), (4, 4));
+        assert_eq!((Poseidon2BabyBear::rate(), Poseidon2BabyBear::capacity()), (8, 8));
+
+        // 7 bytes per element and the length: [m0, m1, m2, 20] in one block
+        let message = random_bytes(20);
+        let mut state: Vec<FpGoldiLocks> = pack_bytes(&message);
+        state.push(FpGoldiLocks::from(20u64));
+        state.resize(8, FpGoldiLocks::from(0u64));
+        let perm = Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS);
+        let expected = perm.permutation(&state);
+        let digest = Poseidon2Goldilocks::digest(&message);
+        for (chunk, el) in digest.chunks(8).zip(expected[..4].iter()) {
+            assert_eq!(from_canonical_bytes::<FpGoldiLocks>(chunk), Some(*el));
+        }
+    }
+
+    #[test]
+    fn streaming() {
+        let message = random_bytes(200);
+        let expected = Poseidon2Goldilocks::digest(&message);
+        for split in [1, 6, 7, 8, 50] {
+            let mut hasher = Poseidon2Goldilocks::new();
+            message.chunks(split).for_each(|c| Digest::update(&mut hasher, c));
+            assert_eq!(hasher.finalize(), expected);
+        }
+
+        let mut hasher = Poseidon2BabyBear::new();
+        io::copy(&mut &message[..], &mut hasher).unwrap();
+        assert_eq!(hasher.finalize_reset(), Poseidon2BabyBear::digest(&message));
+        assert_eq!(hasher.finalize(), Poseidon2BabyBear::digest(b""));
+    }
+
+    #[test]
+    fn injective_packing() {
+        // trailing zeros and chunk boundaries change the length element
+        let digests = [
+            Poseidon2Bn256::digest(b""),
+            Poseidon2Bn256::digest([0u8]),
+            Poseidon2Bn256::digest([0u8; 31]),
+            Poseidon2Bn256::digest([0u8; 32]),
+            Poseidon2Bn256::digest([0u8; 62]),
+        ];
+        for i in 0..digests.len() {
+            for j in 0..i {
+                assert_ne!(digests[i], digests[j]);
+            }
+        }
+
+        let digest = Poseidon2Goldilocks::digest(random_bytes(13));
+        for chunk in digest.chunks(8) {
+            assert!(from_canonical_bytes::<FpGoldiLocks>(chunk).is_some());
+        }
+    }
+
+    #[test]
+    fn f2_tree() {
+        let leaves: Vec<_> = (0..8u32).map(|i| Poseidon2Pallas::digest(i.to_le_bytes())).collect();
+        let mut mt = MerkleTree::<Poseidon2Pallas>::new();
+        let root = mt.accumulate(&leaves);
+        let tree = mt.build_with_cap(&leaves, 0);
+        assert_eq!(mt.climb(3, &leaves[3], &tree.prove(3).siblings), root);
+        assert_ne!(MerkleTree::<Poseidon2Bls12>::new().accumulate(&leaves), root);
+    }
+}
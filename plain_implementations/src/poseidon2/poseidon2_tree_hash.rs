@@ -0,0 +1,217 @@
+use ark_ff::PrimeField;
+use std::thread;
+
+use super::poseidon2::Poseidon2;
+use crate::fields::utils::pack_bytes;
+use crate::merkle_tree::merkle_tree_fp::{node_tag, MerkleTreeHash};
+
+// Tree-mode hash over Poseidon2 for long inputs. For an input of n elements
+// and a chunk length L:
+//  1. The input is split into chunks of L elements, the last chunk may be
+//     shorter. The empty input consists of a single empty chunk.
+//  2. Chunk i is hashed by a sponge with rate t-1 whose capacity element (the
+//     last state element) is initialized with 2^b + i, where b = min(63,
+//     floor(log2(p)) - 1) is a dedicated bit above all node and final tags, so
+//     chunk indices never collide with them. Every block of t-1 elements, the
+//     last one padded with zeros, is added to the state and followed by a
+//     permutation (one permutation for the empty chunk). The chunk digest is
+//     the first state element.
+//  3. The chunk digests are combined level by level, starting at level 0: pairs
+//     of neighbours are compressed with [left, right, node_tag(level)] as in
+//     the tagged Fp Merkle tree, an odd last digest moves up unchanged.
+//  4. The output is the compression of [root, n, tag] with tag 3 for element
+//     inputs and 4 for byte inputs, where n is the length in elements or bytes.
+// Bytes are packed into elements with fields::utils::pack_bytes. Chunks are
+// independent, which lets the chunk phase run in parallel.
+
+pub const CHUNK_LEN: usize = 1024;
+const ELEMENTS_TAG: u64 = 3;
+const BYTES_TAG: u64 = 4;
+
+// bit b of the capacity element of chunk digests
+fn chunk_domain_bit<F: PrimeField>() -> u32 {
+    (F::MODULUS_BIT_SIZE - 2).min(63)
+}
+
+#[derive(Clone, Debug)]
+pub struct Poseidon2TreeHash<F: PrimeField> {
+    perm: Poseidon2<F>,
+    chunk_len: usize,
+}
+
+impl<F: PrimeField> Poseidon2TreeHash<F> {
+    pub fn new(perm: Poseidon2<F>) -> Self {
+        Self::with_chunk_len(perm, CHUNK_LEN)
+    }
+
+    pub fn with_chunk_len(perm: Poseidon2<F>, chunk_len: usize) -> Self {
+        assert!(perm.get_t() >= 3, "the compression needs a tag element");
+        assert!(chunk_len >= 1);
+        Poseidon2TreeHash { perm, chunk_len }
+    }
+
+    pub fn perm(&self) -> &Poseidon2<F> {
+        &self.perm
+    }
+
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    pub fn chunk_digest(&self, index: usize, chunk: &[F]) -> F {
+        let bit = chunk_domain_bit::<F>();
+        assert!((index as u64) < 1 << bit, "too many chunks");
+        let iv = F::from((1u64 << bit) + index as u64);
+        self.perm.sponge(chunk, iv, self.perm.get_t() - 1, 1)[0]
+    }
+
+    pub fn combine(&self, digests: &[F]) -> F {
+        assert!(!digests.is_empty());
+        let mut nodes = digests.to_owned();
+        let mut level = 0;
+        while nodes.len() > 1 {
+            let tag = node_tag(level, None);
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| match pair {
+                    [l, r] => self.perm.compress_tagged(&[l, r], &tag),
+                    [odd] => odd.to_owned(),
+                    _ => unreachable!(),
+                })
+                .collect();
+            level += 1;
+        }
+        nodes[0]
+    }
+
+    fn chunk_digests(&self, input: &[F], threads: usize) -> Vec<F> {
+        if input.is_empty() {
+            return vec![self.chunk_digest(0, &[])];
+        }
+        let chunks: Vec<&[F]> = input.chunks(self.chunk_len).collect();
+        let per_thread = chunks.len().div_ceil(threads.max(1));
+        if threads <= 1 || per_thread >= chunks.len() {
+            return chunks.iter().enumerate().map(|(i, c)| self.chunk_digest(i, c)).collect();
+        }
+
+        thread::scope(|s| {
+            let handles: Vec<_> = chunks
+                .chunks(per_thread)
+                .enumerate()
+                .map(|(j, group)| {
+                    s.spawn(move || {
+                        group
+                            .iter()
+                            .enumerate()
+                            .map(|(i, c)| self.chunk_digest(j * per_thread + i, c))
+                            .collect::<Vec<F>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    fn finalize(&self, root: &F, len: usize, tag: u64) -> F {
+        self.perm.compress_tagged(&[root, &F::from(len as u64)], &F::from(tag))
+    }
+
+    pub fn hash(&self, input: &[F]) -> F {
+        self.hash_parallel(input, 1)
+    }
+
+    pub fn hash_parallel(&self, input: &[F], threads: usize) -> F {
+        let root = self.combine(&self.chunk_digests(input, threads));
+        self.finalize(&root, input.len(), ELEMENTS_TAG)
+    }
+
+    pub fn hash_bytes(&self, input: &[u8]) -> F {
+        self.hash_bytes_parallel(input, 1)
+    }
+
+    pub fn hash_bytes_parallel(&self, input: &[u8], threads: usize) -> F {
+        let root = self.combine(&self.chunk_digests(&pack_bytes(input), threads));
+        self.finalize(&root, input.len(), BYTES_TAG)
+    }
+}
+
+#[cfg(test)]
+mod poseidon2_tree_hash_tests {
+    use super::*;
+    use crate::fields::{bn256::FpBN256, goldilocks::FpGoldiLocks, utils::random_scalar};
+    use crate::poseidon2::poseidon2_instance_bn256::POSEIDON2_BN256_PARAMS;
+    use crate::poseidon2::poseidon2_instance_goldilocks::POSEIDON2_GOLDILOCKS_8_PARAMS;
+
+    type Scalar = FpBN256;
+
+    #[test]
+    fn specification() {
+        let perm = Poseidon2::new(&POSEIDON2_BN256_PARAMS);
+        let hasher = Poseidon2TreeHash::with_chunk_len(perm.clone(), 3);
+        let input: Vec<Scalar> = (0..7).map(|_| random_scalar()).collect();
+
+        // chunks [x0, x1, x2], [x3, x4, x5], [x6] absorbed with rate 2
+        let chunk = |i: u64, blocks: &[&[Scalar]]| {
+            let iv = Scalar::from(1u64 << 63) + Scalar::from(i);
+            let mut state = vec![Scalar::from(0u64), Scalar::from(0u64), iv];
+            for block in blocks {
+                for (s, el) in state.iter_mut().zip(block.iter()) {
+                    *s += el;
+                }
+                state = perm.permutation(&state);
+            }
+            state[0]
+        };
+        let c0 = chunk(0, &[&input[0..2], &input[2..3]]);
+        let c1 = chunk(1, &[&input[3..5], &input[5..6]]);
+        let c2 = chunk(2, &[&input[6..7]]);
+        let tag = |level: u64| Scalar::from(1u64 << 62) + Scalar::from(2 + 256 * level);
+        let left = perm.compress_tagged(&[&c0, &c1], &tag(0));
+        let root = perm.compress_tagged(&[&left, &c2], &tag(1));
+        let expected = perm.compress_tagged(&[&root, &Scalar::from(7u64)], &Scalar::from(3u64));
+        assert_eq!(hasher.hash(&input), expected);
+    }
+
+    #[test]
+    fn parallel() {
+        let hasher = Poseidon2TreeHash::with_chunk_len(Poseidon2::new(&POSEIDON2_GOLDILOCKS_8_PARAMS), 16);
+        let input: Vec<FpGoldiLocks> = (0..1000).map(|_| random_scalar()).collect();
+        let expected = hasher.hash(&input);
+        for threads in [2, 3, 8, 100] {
+            assert_eq!(hasher.hash_parallel(&input, threads), expected);
+        }
+
+        let bytes: Vec<u8> = (0..5000).map(|_| random::random()).collect();
+        assert_eq!(hasher.hash_bytes_parallel(&bytes, 4), hasher.hash_bytes(&bytes));
+    }
+
+    #[test]
+    fn domain_separation() {
+        let hasher = Poseidon2TreeHash::with_chunk_len(Poseidon2::new(&POSEIDON2_BN256_PARAMS), 2);
+        let input: Vec<Scalar> = (0..4).map(|_| random_scalar()).collect();
+        let mut extended = input.clone();
+        extended.push(Scalar::from(0u64));
+        let swapped = vec![input[2], input[3], input[0], input[1]];
+
+        let h = hasher.hash(&input);
+        assert_ne!(hasher.hash(&extended), h);
+        assert_ne!(hasher.hash(&swapped), h);
+        assert_ne!(hasher.hash(&[]), hasher.hash(&[Scalar::from(0u64)]));
+
+        // chunk digests depend on the index
+        assert_ne!(hasher.chunk_digest(0, &input[..2]), hasher.chunk_digest(1, &input[..2]));
+
+        // and are neither inner nodes nor final compressions
+        let pair = [input[0], input[1]];
+        assert_ne!(hasher.chunk_digest(2, &pair), hasher.combine(&pair));
+        let root = input[0];
+        let final_input = [root, Scalar::from(7u64)];
+        assert_ne!(hasher.chunk_digest(3, &final_input), hasher.finalize(&root, 7, ELEMENTS_TAG));
+        assert_ne!(hasher.chunk_digest(4, &final_input), hasher.finalize(&root, 7, BYTES_TAG));
+
+        // bytes and their packed elements are separated
+        let bytes = [1u8, 2, 3];
+        assert_ne!(hasher.hash_bytes(&bytes), hasher.hash(&pack_bytes::<Scalar>(&bytes)));
+        assert_ne!(hasher.hash_bytes(&[1u8]), hasher.hash_bytes(&[1u8, 0]));
+    }
+}
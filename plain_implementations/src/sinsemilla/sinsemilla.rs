@@ -0,0 +1,217 @@
+use lazy_static::lazy_static;
+use pasta_curves::{
+    arithmetic::{CurveAffine, CurveExt},
+    group::{ff::PrimeField, Curve},
+    pallas,
+};
+
+use crate::merkle_tree::merkle_tree_orchard::MerkleTreeHash;
+use crate::utils::{i2lebsp_bytes, i2lebsp_u64};
+
+// Sinsemilla as specified in the Zcash protocol specification, Section 5.4.1.9.
+// Messages are bit strings which are split into chunks of K bits, each chunk
+// selects one of 2^K fixed generators S(j).
+
+pub const K: usize = 10;
+// maximal number of chunks of a message
+pub const C: usize = 253;
+
+const Q_PERSONALIZATION: &str = "z.cash:SinsemillaQ";
+const S_PERSONALIZATION: &str = "z.cash:SinsemillaS";
+pub const MERKLE_CRH_PERSONALIZATION: &str = "z.cash:Orchard-MerkleCRH";
+pub const MERKLE_DEPTH_ORCHARD: usize = 32;
+
+lazy_static! {
+    // S(j) = GroupHash("z.cash:SinsemillaS", I2LEOSP_32(j))
+    static ref S_TABLE: Vec<pallas::Affine> = {
+        let hasher = pallas::Point::hash_to_curve(S_PERSONALIZATION);
+        (0..1u32 << K).map(|j| hasher(&j.to_le_bytes()).to_affine()).collect()
+    };
+}
+
+// I2LEBSP_l(x) of the canonical representation
+pub fn i2lebsp(l: usize, x: &pallas::Base) -> Vec<bool> {
+    i2lebsp_bytes(l, &x.to_repr())
+}
+
+// Extract_P, the x-coordinate or zero for the identity
+pub fn extract_p(point: &pallas::Point) -> pallas::Base {
+    point
+        .to_affine()
+        .coordinates()
+        .map(|c| *c.x())
+        .unwrap_or_else(pallas::Base::zero)
+}
+
+// Incomplete addition: None if an input is the identity or both inputs have the
+// same x-coordinate.
+fn incomplete_add(a: &pallas::Point, b: &pallas::Point) -> Option<pallas::Point> {
+    let xa: Option<_> = a.to_affine().coordinates().map(|c| *c.x()).into();
+    let xb: Option<_> = b.to_affine().coordinates().map(|c| *c.x()).into();
+    let (xa, xb): (pallas::Base, pallas::Base) = (xa?, xb?);
+    if xa == xb {
+        return None;
+    }
+    Some(a + b)
+}
+
+#[derive(Clone, Debug)]
+pub struct SinsemillaHash {
+    q: pallas::Point,
+}
+
+impl SinsemillaHash {
+    pub fn new(domain: &str) -> Self {
+        let q = pallas::Point::hash_to_curve(Q_PERSONALIZATION)(domain.as_bytes());
+        SinsemillaHash { q }
+    }
+
+    pub fn q(&self) -> &pallas::Point {
+        &self.q
+    }
+
+    // None stands for the failure result of the specification.
+    pub fn hash_to_point(&self, message: &[bool]) -> Option<pallas::Point> {
+        let n = message.len().div_ceil(K);
+        assert!(n <= C, "message too long");
+
+        let mut acc = self.q;
+        for i in 0..n {
+            let m = (0..K).fold(0usize, |m, j| {
+                let bit = message.get(i * K + j).cloned().unwrap_or(false);
+                m | ((bit as usize) << j)
+            });
+            let s = pallas::Point::from(S_TABLE[m]);
+            let sum = incomplete_add(&acc, &s)?;
+            acc = incomplete_add(&sum, &acc)?;
+        }
+        Some(acc)
+    }
+
+    pub fn hash(&self, message: &[bool]) -> Option<pallas::Base> {
+        self.hash_to_point(message).map(|p| extract_p(&p))
+    }
+}
+
+// SinsemillaHashToPoint(D || "-M", M) + [r] GroupHash(D || "-r", "")
+#[derive(Clone, Debug)]
+pub struct SinsemillaCommit {
+    hasher: SinsemillaHash,
+    r: pallas::Point,
+}
+
+impl SinsemillaCommit {
+    pub fn new(domain: &str) -> Self {
+        SinsemillaCommit {
+            hasher: SinsemillaHash::new(&format!("{}-M", domain)),
+            r: pallas::Point::hash_to_curve(&format!("{}-r", domain))(&[]),
+        }
+    }
+
+    pub fn commit(&self, message: &[bool], r: &pallas::Scalar) -> Option<pallas::Point> {
+        self.hasher.hash_to_point(message).map(|p| p + self.r * r)
+    }
+
+    pub fn short_commit(&self, message: &[bool], r: &pallas::Scalar) -> Option<pallas::Base> {
+        self.commit(message, r).map(|p| extract_p(&p))
+    }
+}
+
+// MerkleCRH^Orchard: SinsemillaHash of I2LEBSP_10(level) || I2LEBSP_255(left) ||
+// I2LEBSP_255(right), where level is the height of the inputs (0 for leaves),
+// i.e., MerkleDepth^Orchard - 1 - layer. The failure result is mapped to 0.
+#[derive(Clone, Debug)]
+pub struct OrchardMerkleCrh {
+    hasher: SinsemillaHash,
+}
+
+impl Default for OrchardMerkleCrh {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrchardMerkleCrh {
+    pub fn new() -> Self {
+        OrchardMerkleCrh {
+            hasher: SinsemillaHash::new(MERKLE_CRH_PERSONALIZATION),
+        }
+    }
+
+    // value of an unused leaf
+    pub fn uncommitted() -> pallas::Base {
+        pallas::Base::from(2u64)
+    }
+
+    // roots of the empty trees of height 0 to `depth`
+    pub fn empty_roots(&self, depth: usize) -> Vec<pallas::Base> {
+        let mut roots = vec![Self::uncommitted()];
+        for level in 0..depth {
+            let last = roots[level];
+            roots.push(self.compress(level, &[&last, &last]));
+        }
+        roots
+    }
+}
+
+impl MerkleTreeHash for OrchardMerkleCrh {
+    fn compress(&self, level: usize, input: &[&pallas::Base; 2]) -> pallas::Base {
+        let mut message = i2lebsp_u64(10, level as u64);
+        message.extend(i2lebsp(255, input[0]));
+        message.extend(i2lebsp(255, input[1]));
+        self.hasher.hash(&message).unwrap_or_else(pallas::Base::zero)
+    }
+}
+
+#[cfg(test)]
+mod sinsemilla_tests {
+    use super::*;
+    use crate::merkle_tree::merkle_tree_orchard::MerkleTree;
+    use pasta_curves::group::ff::Field;
+
+    fn from_le_hex(s: &str) -> pallas::Base {
+        let mut repr = [0u8; 32];
+        repr.copy_from_slice(&hex::decode(s).unwrap());
+        pallas::Base::from_repr(repr).unwrap()
+    }
+
+    #[test]
+    fn orchard_empty_root() {
+        let crh = OrchardMerkleCrh::new();
+        let roots = crh.empty_roots(MERKLE_DEPTH_ORCHARD);
+        assert_eq!(
+            roots[MERKLE_DEPTH_ORCHARD],
+            from_le_hex("ae2935f1dfd8a24aed7c70df7de3a668eb7a49b1319880dde2bbd9031ae5d82f")
+        );
+
+        let mut mt = MerkleTree::new(crh);
+        let leaves = vec![OrchardMerkleCrh::uncommitted(); 8];
+        assert_eq!(mt.accumulate(&leaves), roots[3]);
+    }
+
+    #[test]
+    fn commitments() {
+        let commit = SinsemillaCommit::new("z.cash:test-Commit");
+        let message = i2lebsp_u64(37, 0x0001_2345_6789);
+        let r1 = pallas::Scalar::random(ark_std::rand::thread_rng());
+        let r2 = pallas::Scalar::random(ark_std::rand::thread_rng());
+
+        let hash = SinsemillaHash::new("z.cash:test-Commit-M").hash_to_point(&message).unwrap();
+        assert_eq!(commit.commit(&message, &pallas::Scalar::zero()).unwrap(), hash);
+        let c1 = commit.commit(&message, &r1).unwrap();
+        let c2 = commit.commit(&message, &r2).unwrap();
+        assert_eq!(c1 - c2, commit.r * (r1 - r2));
+        assert_ne!(commit.short_commit(&message, &r1), commit.short_commit(&message, &r2));
+    }
+
+    #[test]
+    fn chunk_padding() {
+        // messages are padded with zero bits to a multiple of K bits
+        let hasher = SinsemillaHash::new("z.cash:test");
+        let message = vec![true, false, true];
+        let mut padded = message.clone();
+        padded.resize(K, false);
+        assert_eq!(hasher.hash(&message), hasher.hash(&padded));
+        assert_eq!(hasher.hash(&[]), Some(extract_p(hasher.q())));
+    }
+}
@@ -6,6 +6,16 @@ use ark_ff::PrimeField;
 //     F::from_repr(F::Repr::from(val)).unwrap()
 // }
 
+// I2LEBSP_l of the integer with the given little-endian bytes, bits beyond the
+// bytes are zero
+pub fn i2lebsp_bytes(l: usize, bytes: &[u8]) -> Vec<bool> {
+    (0..l).map(|i| i < 8 * bytes.len() && (bytes[i / 8] >> (i % 8)) & 1 == 1).collect()
+}
+
+pub fn i2lebsp_u64(l: usize, x: u64) -> Vec<bool> {
+    i2lebsp_bytes(l, &x.to_le_bytes())
+}
+
 // gaussian elimination
 pub fn mat_inverse<F: PrimeField>(mat: &[Vec<F>]) -> Vec<Vec<F>> {
     let n = mat.len();
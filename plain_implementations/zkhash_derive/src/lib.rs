@@ -0,0 +1,108 @@
+//! # zkhash_derive
+//!
+//! Derive macro for `zkhash::fields::to_field_elements::ToFieldElements`
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, GenericParam, Ident, Index};
+
+// Structs encode their fields in declaration order. Enums encode the index of
+// the variant as a single element, followed by the fields of the variant. The
+// implementation is generic over the field and requires every field type to
+// implement the trait for it.
+#[proc_macro_derive(ToFieldElements)]
+pub fn derive_to_field_elements(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let field = Ident::new("__F", Span::call_site());
+    let trait_path = quote!(::zkhash::fields::to_field_elements::ToFieldElements<#field>);
+
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in field_types(&input.data) {
+        where_clause.predicates.push(parse_quote!(#ty: #trait_path));
+    }
+    let where_clause = generics.where_clause.clone();
+    generics
+        .params
+        .push(GenericParam::Type(parse_quote!(#field: ::zkhash::ark_ff::PrimeField)));
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, writes) = destructure(&data.fields, &trait_path);
+            quote! {
+                let #name #pattern = self;
+                #(#writes)*
+            }
+        }
+        Data::Enum(data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let ident = &variant.ident;
+                let index = index as u64;
+                let (pattern, writes) = destructure(&variant.fields, &trait_path);
+                quote! {
+                    #name::#ident #pattern => {
+                        out.push(#field::from(#index));
+                        #(#writes)*
+                    }
+                }
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return syn::Error::new_spanned(&input.ident, "ToFieldElements can not be derived for unions")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let expanded = quote! {
+        impl #impl_generics #trait_path for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn write_elements(&self, out: &mut ::std::vec::Vec<#field>) {
+                #body
+            }
+        }
+    };
+    expanded.into()
+}
+
+fn field_types(data: &Data) -> Vec<syn::Type> {
+    match data {
+        Data::Struct(data) => data.fields.iter().map(|f| f.ty.clone()).collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|v| v.fields.iter().map(|f| f.ty.clone()))
+            .collect(),
+        Data::Union(_) => vec![],
+    }
+}
+
+// A pattern binding every field and the statements writing them in order.
+fn destructure(fields: &Fields, trait_path: &TokenStream2) -> (TokenStream2, Vec<TokenStream2>) {
+    let bindings: Vec<Ident> = (0..fields.len()).map(|i| format_ident!("__field{}", i)).collect();
+    let writes = bindings
+        .iter()
+        .map(|b| quote!(<_ as #trait_path>::write_elements(#b, out);))
+        .collect();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| f.ident.as_ref().unwrap());
+            quote!({ #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => {
+            let indices = (0..fields.len()).map(Index::from);
+            quote!({ #(#indices: #bindings),* })
+        }
+        Fields::Unit => quote!(),
+    };
+    (pattern, writes)
+}